@@ -7,13 +7,13 @@ use ethers::{
     abi::RawLog,
     prelude::EthEvent,
     providers::Middleware,
-    types::{Log, H160, H256, U256},
+    types::{Log, H160, H256, U256, U512},
 };
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 use crate::{
-    amm::AutomatedMarketMaker,
+    amm::{AmmState, AutomatedMarketMaker, SanityIssue, TokenPair, MAX_TOKEN_DECIMALS},
     errors::{AMMError, ArithmeticError, EventLogError, SwapSimulationError},
 };
 
@@ -33,6 +33,10 @@ abigen!(
     ]"#;
 );
 
+/// Average block time used to annualize `ERC4626Vault::apy_estimate`.
+pub const AVERAGE_BLOCK_TIME_SECONDS: u64 = 12;
+pub const SECONDS_PER_YEAR: u64 = 31_536_000;
+
 pub const DEPOSIT_EVENT_SIGNATURE: H256 = H256([
     220, 188, 28, 5, 36, 15, 49, 255, 58, 208, 103, 239, 30, 227, 92, 228, 153, 119, 98, 117, 46,
     58, 9, 82, 132, 117, 69, 68, 244, 199, 9, 215,
@@ -65,6 +69,14 @@ impl AutomatedMarketMaker for ERC4626Vault {
         vec![self.vault_token, self.asset_token]
     }
 
+    //Reserves exceeding u128 are saturated
+    fn reserves(&self) -> Vec<u128> {
+        [self.vault_reserve, self.asset_reserve]
+            .into_iter()
+            .map(|reserve| u128::try_from(reserve).unwrap_or(u128::MAX))
+            .collect()
+    }
+
     fn calculate_price(&self, base_token: H160) -> Result<f64, ArithmeticError> {
         Ok(q64_to_f64(self.calculate_price_64_x_64(base_token)?))
     }
@@ -152,6 +164,75 @@ impl AutomatedMarketMaker for ERC4626Vault {
             self.vault_token
         }
     }
+
+    fn token_pair(&self) -> Option<TokenPair> {
+        Some(TokenPair::new(self.vault_token, self.asset_token))
+    }
+
+    fn state_snapshot(&self) -> AmmState {
+        AmmState::ERC4626Vault {
+            vault_reserve: self.vault_reserve,
+            asset_reserve: self.asset_reserve,
+        }
+    }
+
+    fn restore_state(&mut self, state: AmmState) {
+        if let AmmState::ERC4626Vault {
+            vault_reserve,
+            asset_reserve,
+        } = state
+        {
+            self.vault_reserve = vault_reserve;
+            self.asset_reserve = asset_reserve;
+        }
+    }
+
+    //Deposit and withdraw fees are already in basis points, the larger one is reported
+    fn fee_bps(&self) -> u32 {
+        self.deposit_fee.max(self.withdraw_fee)
+    }
+
+    //The vault only holds the asset, valued at `asset_reserve` assets or `vault_reserve` shares
+    fn total_value_locked(&self, base_token: H160) -> Result<f64, ArithmeticError> {
+        let (reserve, decimals) = if base_token == self.vault_token {
+            (self.vault_reserve, self.vault_token_decimals)
+        } else if base_token == self.asset_token {
+            (self.asset_reserve, self.asset_token_decimals)
+        } else {
+            return Err(ArithmeticError::InvalidToken(base_token));
+        };
+
+        let reserve = u128::try_from(reserve).map_err(|_| ArithmeticError::U128ConversionError)?;
+
+        Ok(reserve as f64 / 10_f64.powi(decimals as i32))
+    }
+
+    fn sanity_check(&self) -> Vec<SanityIssue> {
+        let mut issues = vec![];
+
+        if self.vault_reserve.is_zero() || self.asset_reserve.is_zero() {
+            issues.push(SanityIssue::ReservesOutOfRange);
+        }
+
+        for decimals in [self.vault_token_decimals, self.asset_token_decimals] {
+            if decimals > MAX_TOKEN_DECIMALS {
+                issues.push(SanityIssue::DecimalsUnusual(decimals));
+            }
+        }
+
+        //Fees are in basis points
+        for fee in [self.deposit_fee, self.withdraw_fee] {
+            if fee > 10000 {
+                issues.push(SanityIssue::FeeOutOfRange(fee));
+            }
+        }
+
+        if self.vault_token == self.asset_token {
+            issues.push(SanityIssue::TokensSameAddress);
+        }
+
+        issues
+    }
 }
 
 impl ERC4626Vault {
@@ -261,6 +342,64 @@ impl ERC4626Vault {
         }
     }
 
+    /// Returns the amount of assets redeemable per share, adjusted by the token decimals.
+    ///
+    /// Matches the price of the vault token. Returns 1.0 for an empty vault and 0.0 if the rate cannot be calculated.
+    pub fn effective_exchange_rate(&self) -> f64 {
+        self.calculate_price(self.vault_token).unwrap_or_default()
+    }
+
+    /// Returns the relative growth of the exchange rate since the `previous` state of the vault, ie. 0.01 for 1% yield.
+    ///
+    /// Returns 0.0 if the previous exchange rate is zero.
+    pub fn yield_since(&self, previous: &ERC4626Vault) -> f64 {
+        let previous_rate = previous.effective_exchange_rate();
+
+        if previous_rate == 0.0 {
+            return 0.0;
+        }
+
+        (self.effective_exchange_rate() - previous_rate) / previous_rate
+    }
+
+    /// Estimates the APY of a vault from `(block_number, vault_reserve, asset_reserve)` snapshots.
+    ///
+    /// The growth of the exchange rate `asset_reserve / vault_reserve` from the first to the last snapshot is annualized,
+    /// assuming blocks are produced every `AVERAGE_BLOCK_TIME_SECONDS`. Returns 0.0 if fewer than two snapshots are provided
+    /// or the exchange rate cannot be calculated.
+    pub fn apy_estimate(historical_snapshots: &[(u64, U256, U256)]) -> f64 {
+        let (Some(first), Some(last)) = (historical_snapshots.first(), historical_snapshots.last())
+        else {
+            return 0.0;
+        };
+
+        if historical_snapshots.len() < 2 || last.0 <= first.0 {
+            return 0.0;
+        }
+
+        let exchange_rate = |vault_reserve: U256, asset_reserve: U256| {
+            if vault_reserve.is_zero() {
+                return 0.0;
+            }
+
+            div_uu(asset_reserve, vault_reserve)
+                .map(q64_to_f64)
+                .unwrap_or_default()
+        };
+
+        let first_rate = exchange_rate(first.1, first.2);
+        let last_rate = exchange_rate(last.1, last.2);
+
+        if first_rate == 0.0 || last_rate == 0.0 {
+            return 0.0;
+        }
+
+        let blocks_per_year = (SECONDS_PER_YEAR / AVERAGE_BLOCK_TIME_SECONDS) as f64;
+        let elapsed_blocks = (last.0 - first.0) as f64;
+
+        (last_rate / first_rate).powf(blocks_per_year / elapsed_blocks) - 1.0
+    }
+
     pub fn get_amount_out(&self, amount_in: U256, reserve_in: U256, reserve_out: U256) -> U256 {
         if amount_in.is_zero() {
             return U256::zero();
@@ -280,18 +419,322 @@ impl ERC4626Vault {
     }
 }
 
+/// Share and asset conversions of an ERC4626 vault, computed from the local reserve state.
+pub trait ERC4626 {
+    /// Returns the shares received for depositing `assets`, equivalent to `previewDeposit`.
+    fn calculate_shares_for_assets(&self, assets: U256) -> Result<U256, ArithmeticError>;
+
+    /// Returns the assets received for redeeming `shares`, equivalent to `previewRedeem`.
+    fn calculate_assets_for_shares(&self, shares: U256) -> Result<U256, ArithmeticError>;
+}
+
+/// Converts `amount` at the `reserve_out / reserve_in` rate, net of `fee` in bps.
+///
+/// Computed in U512, returns `ArithmeticError::Overflow` if the result does not fit in a U256.
+fn convert_with_fee(
+    amount: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee: u32,
+) -> Result<U256, ArithmeticError> {
+    let fee_multiplier = 10000_u32
+        .checked_sub(fee)
+        .ok_or(ArithmeticError::InvalidFee(fee))?;
+
+    let converted = amount.full_mul(reserve_out) / U512::from(reserve_in)
+        * U512::from(fee_multiplier)
+        / U512::from(10000);
+
+    U256::try_from(converted).map_err(|_| ArithmeticError::Overflow)
+}
+
+impl ERC4626 for ERC4626Vault {
+    fn calculate_shares_for_assets(&self, assets: U256) -> Result<U256, ArithmeticError> {
+        if assets.is_zero() {
+            return Ok(U256::zero());
+        }
+
+        //An empty vault mints shares 1:1
+        if self.asset_reserve.is_zero() {
+            return Ok(assets);
+        }
+
+        convert_with_fee(
+            assets,
+            self.asset_reserve,
+            self.vault_reserve,
+            self.deposit_fee,
+        )
+    }
+
+    fn calculate_assets_for_shares(&self, shares: U256) -> Result<U256, ArithmeticError> {
+        if shares.is_zero() {
+            return Ok(U256::zero());
+        }
+
+        if self.vault_reserve.is_zero() {
+            return Ok(shares);
+        }
+
+        convert_with_fee(
+            shares,
+            self.vault_reserve,
+            self.asset_reserve,
+            self.withdraw_fee,
+        )
+    }
+}
+
+/// A decoded ERC4626 `Deposit` or `Withdraw` event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VaultEvent {
+    Deposit {
+        assets: U256,
+        shares: U256,
+        owner: H160,
+    },
+    Withdraw {
+        assets: U256,
+        shares: U256,
+        receiver: H160,
+    },
+}
+
+/// Decodes an ERC4626 `Deposit` or `Withdraw` event log.
+pub fn decode_vault_event(log: &Log) -> Result<VaultEvent, EventLogError> {
+    let event_signature = log
+        .topics
+        .first()
+        .ok_or(EventLogError::InvalidEventSignature)?;
+
+    if *event_signature == DEPOSIT_EVENT_SIGNATURE {
+        let deposit_event = DepositFilter::decode_log(&RawLog::from(log.clone()))?;
+        Ok(VaultEvent::Deposit {
+            assets: deposit_event.assets,
+            shares: deposit_event.shares,
+            owner: deposit_event.owner,
+        })
+    } else if *event_signature == WITHDRAW_EVENT_SIGNATURE {
+        let withdraw_event = WithdrawFilter::decode_log(&RawLog::from(log.clone()))?;
+        Ok(VaultEvent::Withdraw {
+            assets: withdraw_event.assets,
+            shares: withdraw_event.shares,
+            receiver: withdraw_event.receiver,
+        })
+    } else {
+        Err(EventLogError::InvalidEventSignature)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{str::FromStr, sync::Arc};
 
     use ethers::{
+        abi::Token,
         providers::{Http, Provider},
-        types::{H160, U256},
+        types::{Log, H160, H256, U256},
     };
 
-    use crate::amm::AutomatedMarketMaker;
+    use crate::{amm::AutomatedMarketMaker, errors::ArithmeticError};
+
+    use super::{
+        decode_vault_event, ERC4626Vault, VaultEvent, AVERAGE_BLOCK_TIME_SECONDS,
+        DEPOSIT_EVENT_SIGNATURE, ERC4626, SECONDS_PER_YEAR, WITHDRAW_EVENT_SIGNATURE,
+    };
+
+    #[test]
+    fn test_apy_estimate() {
+        let blocks_per_year = SECONDS_PER_YEAR / AVERAGE_BLOCK_TIME_SECONDS;
+        let shares = U256::from(1_000_000_000_000_u64);
+
+        //The exchange rate grows 5% over half a year
+        let apy = ERC4626Vault::apy_estimate(&[
+            (100, shares, U256::from(1_000_000_000_000_u64)),
+            (
+                100 + blocks_per_year / 4,
+                shares,
+                U256::from(1_020_000_000_000_u64),
+            ),
+            (
+                100 + blocks_per_year / 2,
+                shares,
+                U256::from(1_050_000_000_000_u64),
+            ),
+        ]);
+        assert!((apy - 0.1025).abs() < 1e-6);
+
+        assert_eq!(ERC4626Vault::apy_estimate(&[]), 0.0);
+        assert_eq!(
+            ERC4626Vault::apy_estimate(&[(100, shares, U256::from(1_000_000_000_000_u64))]),
+            0.0
+        );
+        assert_eq!(
+            ERC4626Vault::apy_estimate(&[(100, U256::zero(), shares), (200, shares, shares)]),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_share_asset_conversions() -> eyre::Result<()> {
+        let vault = ERC4626Vault {
+            vault_token: H160::from_low_u64_be(1),
+            vault_token_decimals: 18,
+            asset_token: H160::from_low_u64_be(2),
+            asset_token_decimals: 18,
+            vault_reserve: U256::from_dec_str("501910315708981197269904")?,
+            asset_reserve: U256::from_dec_str("505434849031054568651911")?,
+            ..Default::default()
+        };
+
+        let amount = U256::from_dec_str("3000000000000000000")?;
+
+        let shares_out = vault.calculate_shares_for_assets(amount)?;
+        let assets_out = vault.calculate_assets_for_shares(amount)?;
+
+        assert_eq!(shares_out, U256::from_dec_str("2979080192063348487")?);
+        assert_eq!(assets_out, U256::from_dec_str("3021066711791496478")?);
+        assert_eq!(shares_out, vault.simulate_swap(vault.asset_token, amount)?);
+        assert_eq!(assets_out, vault.simulate_swap(vault.vault_token, amount)?);
+
+        let vault = ERC4626Vault {
+            deposit_fee: 100,
+            withdraw_fee: 100,
+            ..vault
+        };
+
+        assert_eq!(
+            vault.calculate_shares_for_assets(amount)?,
+            shares_out * 9900 / 10000
+        );
+        assert_eq!(
+            vault.calculate_assets_for_shares(amount)?,
+            assets_out * 9900 / 10000
+        );
+
+        //Fees over 100% and conversions overflowing a U256 are errors rather than panics
+        let vault = ERC4626Vault {
+            deposit_fee: 10001,
+            ..vault
+        };
+        assert!(matches!(
+            vault.calculate_shares_for_assets(amount),
+            Err(ArithmeticError::InvalidFee(10001))
+        ));
+        let vault = ERC4626Vault {
+            asset_reserve: vault.vault_reserve * 2,
+            withdraw_fee: 0,
+            ..vault
+        };
+        assert!(matches!(
+            vault.calculate_assets_for_shares(U256::MAX),
+            Err(ArithmeticError::Overflow)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_total_value_locked() {
+        let vault = ERC4626Vault {
+            vault_token: H160::from_low_u64_be(1),
+            vault_token_decimals: 18,
+            asset_token: H160::from_low_u64_be(2),
+            asset_token_decimals: 6,
+            vault_reserve: U256::from(900_000_000_000_000_000_000_u128),
+            asset_reserve: U256::from(1_000_000_000_u64),
+            ..Default::default()
+        };
+
+        assert_eq!(vault.total_value_locked(vault.vault_token).unwrap(), 900.0);
+        assert_eq!(
+            vault.total_value_locked(vault.asset_token).unwrap(),
+            1_000.0
+        );
+        assert!(vault.total_value_locked(H160::from_low_u64_be(3)).is_err());
+    }
 
-    use super::ERC4626Vault;
+    #[test]
+    fn test_effective_exchange_rate() -> eyre::Result<()> {
+        let previous = ERC4626Vault {
+            vault_token: H160::from_low_u64_be(1),
+            vault_token_decimals: 18,
+            asset_token: H160::from_low_u64_be(2),
+            asset_token_decimals: 6,
+            vault_reserve: U256::from_dec_str("501910315708981197269904")?,
+            asset_reserve: U256::from_dec_str("505434849031")?,
+            ..Default::default()
+        };
+
+        //Consistent with the price of the vault token
+        assert_eq!(previous.effective_exchange_rate(), 1.0070222372637234);
+        assert_eq!(
+            previous.effective_exchange_rate(),
+            previous.calculate_price(previous.vault_token)?
+        );
+
+        let current = ERC4626Vault {
+            asset_reserve: U256::from_dec_str("510489197521")?,
+            ..previous.clone()
+        };
+
+        assert!((current.yield_since(&previous) - 0.01).abs() < 1e-6);
+        assert_eq!(previous.yield_since(&previous), 0.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_vault_event() -> eyre::Result<()> {
+        let sender = H160::from_low_u64_be(1);
+        let owner = H160::from_low_u64_be(2);
+        let receiver = H160::from_low_u64_be(3);
+        let data =
+            ethers::abi::encode(&[Token::Uint(U256::from(1000)), Token::Uint(U256::from(900))]);
+
+        let deposit_log = Log {
+            topics: vec![
+                DEPOSIT_EVENT_SIGNATURE,
+                H256::from(sender),
+                H256::from(owner),
+            ],
+            data: data.clone().into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            decode_vault_event(&deposit_log)?,
+            VaultEvent::Deposit {
+                assets: U256::from(1000),
+                shares: U256::from(900),
+                owner,
+            }
+        );
+
+        let withdraw_log = Log {
+            topics: vec![
+                WITHDRAW_EVENT_SIGNATURE,
+                H256::from(sender),
+                H256::from(receiver),
+                H256::from(owner),
+            ],
+            data: data.into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            decode_vault_event(&withdraw_log)?,
+            VaultEvent::Withdraw {
+                assets: U256::from(1000),
+                shares: U256::from(900),
+                receiver,
+            }
+        );
+
+        assert!(decode_vault_event(&Log::default()).is_err());
+
+        Ok(())
+    }
 
     #[tokio::test]
     async fn test_get_vault_data() -> eyre::Result<()> {
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use async_trait::async_trait;
 use ethers::{
@@ -8,7 +8,10 @@ use ethers::{
 use futures::stream::FuturesUnordered;
 use serde::{Deserialize, Serialize};
 
-use crate::errors::{AMMError, EventLogError};
+use crate::{
+    errors::{AMMError, EventLogError},
+    sync::checkpoint::is_rate_limit_message,
+};
 
 use super::{
     uniswap_v2::factory::{UniswapV2Factory, PAIR_CREATED_EVENT_SIGNATURE},
@@ -45,6 +48,11 @@ pub trait AutomatedMarketMakerFactory {
     /// Returns the block number at which the factory was created.
     fn creation_block(&self) -> u64;
 
+    /// Returns the id of the chain the factory is deployed on, if the factory address is known.
+    fn chain_id(&self) -> Option<u64> {
+        None
+    }
+
     /// Creates a new AMM from a log factory creation event.
     ///
     /// Returns a AMM with data populated.
@@ -111,6 +119,12 @@ macro_rules! factory {
                 }
             }
 
+            fn chain_id(&self) -> Option<u64> {
+                match self {
+                    $(Factory::$factory_type(factory) => factory.chain_id(),)+
+                }
+            }
+
             async fn new_amm_from_log<M: 'static + Middleware>(
                 &self,
                 log: Log,
@@ -132,13 +146,55 @@ macro_rules! factory {
 
 factory!(UniswapV2Factory, UniswapV3Factory);
 
+/// Timeout and retry policy for the log requests made when getting pools from logs.
+#[derive(Debug, Clone, Copy)]
+pub struct LogRequestConfig {
+    // Timeout of each request for a block range
+    pub timeout: Duration,
+    pub max_retries: u32,
+    // Delay before the first retry, doubled after each retry
+    pub initial_retry_delay: Duration,
+}
+
+impl Default for LogRequestConfig {
+    fn default() -> Self {
+        LogRequestConfig {
+            timeout: Duration::from_secs(30),
+            max_retries: 3,
+            initial_retry_delay: Duration::from_secs(1),
+        }
+    }
+}
+
 impl Factory {
     pub async fn get_all_pools_from_logs<M: 'static + Middleware>(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        step: u64,
+        middleware: Arc<M>,
+    ) -> Result<Vec<AMM>, AMMError<M>> {
+        self.get_all_pools_from_logs_with_config(
+            from_block,
+            to_block,
+            step,
+            middleware,
+            LogRequestConfig::default(),
+        )
+        .await
+    }
+
+    /// Gets all pools from the factory created logs from `from_block` to `to_block`, requesting `step` blocks at a time.
+    ///
+    /// Block ranges timing out are retried according to the `config`. If any range still times out, `AMMError::BatchRequestFailed`
+    /// is returned listing the failed ranges.
+    pub async fn get_all_pools_from_logs_with_config<M: 'static + Middleware>(
         &self,
         mut from_block: u64,
         to_block: u64,
         step: u64,
         middleware: Arc<M>,
+        config: LogRequestConfig,
     ) -> Result<Vec<AMM>, AMMError<M>> {
         let factory_address = self.address();
         let amm_created_event_signature = self.amm_created_event_signature();
@@ -155,27 +211,135 @@ impl Factory {
 
             let filter = Filter::new()
                 .topic0(ValueOrArray::Value(amm_created_event_signature))
-                .address(factory_address)
-                .from_block(BlockNumber::Number(U64([from_block])))
-                .to_block(BlockNumber::Number(U64([target_block])));
+                .address(factory_address);
 
-            futures.push(async move { middleware.get_logs(&filter).await });
+            futures.push(async move {
+                get_logs_with_timeout(filter, from_block, target_block, middleware, config).await
+            });
 
             from_block += step;
         }
 
+        let mut failed_ranges = vec![];
         while let Some(result) = futures.next().await {
-            let logs = result.map_err(AMMError::MiddlewareError)?;
+            let logs = match result {
+                Ok(logs) => logs,
+                Err(AMMError::Timeout(from_block, to_block)) => {
+                    failed_ranges.push((from_block, to_block));
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
 
             for log in logs {
                 aggregated_amms.push(self.new_empty_amm_from_log(log)?);
             }
         }
 
+        if !failed_ranges.is_empty() {
+            failed_ranges.sort_unstable();
+            return Err(AMMError::BatchRequestFailed(failed_ranges));
+        }
+
         Ok(aggregated_amms)
     }
 }
 
+/// Gets the logs matching the `filter` from `from_block` to `to_block`, timing out and retrying the request according to the `config`.
+///
+/// Returns `AMMError::Timeout` if the request still times out after all retries.
+pub async fn get_logs_with_timeout<M: Middleware>(
+    filter: Filter,
+    from_block: u64,
+    to_block: u64,
+    middleware: Arc<M>,
+    config: LogRequestConfig,
+) -> Result<Vec<Log>, AMMError<M>> {
+    let mut retry_delay = config.initial_retry_delay;
+
+    for retry in 0..=config.max_retries {
+        if retry > 0 {
+            tracing::warn!(
+                from_block,
+                to_block,
+                retry,
+                "log request timed out, retrying"
+            );
+            tokio::time::sleep(retry_delay).await;
+            retry_delay *= 2;
+        }
+
+        if let Ok(result) = tokio::time::timeout(
+            config.timeout,
+            get_logs_with_range_shrinking(filter.clone(), from_block, to_block, middleware.clone()),
+        )
+        .await
+        {
+            return result;
+        }
+    }
+
+    Err(AMMError::Timeout(from_block, to_block))
+}
+
+/// Gets the logs matching the `filter` from `from_block` to `to_block`.
+///
+/// If the provider rejects the block range as too large, the range is split in half and each half is requested separately.
+pub async fn get_logs_with_range_shrinking<M: Middleware>(
+    filter: Filter,
+    from_block: u64,
+    to_block: u64,
+    middleware: Arc<M>,
+) -> Result<Vec<Log>, AMMError<M>> {
+    let mut logs = vec![];
+    let mut block_ranges = vec![(from_block, to_block)];
+
+    while let Some((from_block, to_block)) = block_ranges.pop() {
+        let range_filter = filter
+            .clone()
+            .from_block(BlockNumber::Number(U64([from_block])))
+            .to_block(BlockNumber::Number(U64([to_block])));
+
+        match middleware.get_logs(&range_filter).await {
+            Ok(range_logs) => logs.extend(range_logs),
+
+            Err(err) if from_block < to_block && is_block_range_error(&err.to_string()) => {
+                let mid_block = from_block + (to_block - from_block) / 2;
+                tracing::debug!(from_block, to_block, "block range too large, splitting");
+
+                //Push the upper half first so the lower half is requested next, keeping the logs in order
+                block_ranges.push((mid_block + 1, to_block));
+                block_ranges.push((from_block, mid_block));
+            }
+
+            Err(err) => return Err(AMMError::MiddlewareError(err)),
+        }
+    }
+
+    Ok(logs)
+}
+
+/// Returns whether the error message indicates that the provider rejected the block range or the number of logs returned.
+///
+/// Rate limit errors are excluded, shrinking the block range would only multiply the requests sent to a throttling provider.
+fn is_block_range_error(message: &str) -> bool {
+    if is_rate_limit_message(message) {
+        return false;
+    }
+
+    let message = message.to_lowercase();
+
+    [
+        "block range",
+        "range is too large",
+        "range too large",
+        "query returned more than",
+        "log response size exceeded",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
 impl TryFrom<H256> for Factory {
     type Error = EventLogError;
 
@@ -189,3 +353,265 @@ impl TryFrom<H256> for Factory {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use ethers::{
+        abi::Token,
+        providers::{JsonRpcClient, JsonRpcError, MockError, Provider},
+        types::{Filter, Log, H160, H256, U256, U64},
+    };
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use crate::{
+        amm::{
+            factory::{get_logs_with_timeout, is_block_range_error, Factory, LogRequestConfig},
+            uniswap_v2::factory::{UniswapV2Factory, PAIR_CREATED_EVENT_SIGNATURE},
+            AutomatedMarketMaker,
+        },
+        errors::AMMError,
+    };
+
+    //Provider returning the logs in the requested range, rejecting ranges spanning more than `max_block_span` blocks
+    #[derive(Debug)]
+    struct RangeLimitedProvider {
+        max_block_span: u64,
+        logs: Vec<Log>,
+    }
+
+    #[async_trait]
+    impl JsonRpcClient for RangeLimitedProvider {
+        type Error = MockError;
+
+        async fn request<T: Serialize + Send + Sync, R: DeserializeOwned>(
+            &self,
+            _method: &str,
+            params: T,
+        ) -> Result<R, MockError> {
+            let params: Vec<Filter> = serde_json::from_value(serde_json::to_value(params)?)?;
+            let from_block = params[0].get_from_block().unwrap_or_default().as_u64();
+            let to_block = params[0].get_to_block().unwrap_or_default().as_u64();
+
+            if to_block - from_block + 1 > self.max_block_span {
+                return Err(MockError::JsonRpcError(JsonRpcError {
+                    code: -32005,
+                    message: "block range is too large".to_string(),
+                    data: None,
+                }));
+            }
+
+            let logs: Vec<&Log> = self
+                .logs
+                .iter()
+                .filter(|log| {
+                    let block_number = log.block_number.unwrap_or_default().as_u64();
+                    block_number >= from_block && block_number <= to_block
+                })
+                .collect();
+
+            Ok(serde_json::from_value(serde_json::to_value(logs)?)?)
+        }
+    }
+
+    //Provider returning the logs in the requested range, stalling on the first request and on ranges starting at `stalled_from_blocks`
+    #[derive(Debug, Default)]
+    struct StallingProvider {
+        stall_first_request: AtomicBool,
+        stalled_from_blocks: Vec<u64>,
+        requests: AtomicUsize,
+        logs: Vec<Log>,
+    }
+
+    #[async_trait]
+    impl JsonRpcClient for StallingProvider {
+        type Error = MockError;
+
+        async fn request<T: Serialize + Send + Sync, R: DeserializeOwned>(
+            &self,
+            _method: &str,
+            params: T,
+        ) -> Result<R, MockError> {
+            let params: Vec<Filter> = serde_json::from_value(serde_json::to_value(params)?)?;
+            let from_block = params[0].get_from_block().unwrap_or_default().as_u64();
+            let to_block = params[0].get_to_block().unwrap_or_default().as_u64();
+
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            if self.stall_first_request.swap(false, Ordering::SeqCst)
+                || self.stalled_from_blocks.contains(&from_block)
+            {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+            }
+
+            let logs: Vec<&Log> = self
+                .logs
+                .iter()
+                .filter(|log| {
+                    let block_number = log.block_number.unwrap_or_default().as_u64();
+                    block_number >= from_block && block_number <= to_block
+                })
+                .collect();
+
+            Ok(serde_json::from_value(serde_json::to_value(logs)?)?)
+        }
+    }
+
+    fn pair_created_logs(factory_address: H160, block_numbers: &[u64]) -> Vec<Log> {
+        block_numbers
+            .iter()
+            .enumerate()
+            .map(|(i, block_number)| {
+                let i = i as u64 + 1;
+                Log {
+                    address: factory_address,
+                    topics: vec![
+                        PAIR_CREATED_EVENT_SIGNATURE,
+                        H256::from(H160::from_low_u64_be(i * 10)),
+                        H256::from(H160::from_low_u64_be(i * 10 + 1)),
+                    ],
+                    data: ethers::abi::encode(&[
+                        Token::Address(H160::from_low_u64_be(i)),
+                        Token::Uint(U256::from(i)),
+                    ])
+                    .into(),
+                    block_number: Some(U64::from(*block_number)),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    const TEST_LOG_REQUEST_CONFIG: LogRequestConfig = LogRequestConfig {
+        timeout: Duration::from_millis(50),
+        max_retries: 2,
+        initial_retry_delay: Duration::from_millis(10),
+    };
+
+    #[test]
+    fn test_is_block_range_error() {
+        assert!(is_block_range_error(
+            "query returned more than 10000 results"
+        ));
+        assert!(is_block_range_error(
+            "eth_getLogs block range exceeds the 2000 block limit"
+        ));
+
+        //Throttling is retried by the caller, not treated as an oversized range
+        assert!(!is_block_range_error("rate limit exceeded"));
+        assert!(!is_block_range_error(
+            "(code: 429, message: Too Many Requests, data: None)"
+        ));
+        assert!(!is_block_range_error(
+            "daily request limit exceeded, rate limited"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_logs_with_timeout_retries() -> eyre::Result<()> {
+        let factory_address = H160::from_low_u64_be(100);
+        let provider = Arc::new(Provider::new(StallingProvider {
+            stall_first_request: AtomicBool::new(true),
+            logs: pair_created_logs(factory_address, &[10, 20]),
+            ..Default::default()
+        }));
+
+        let filter = Filter::new().address(factory_address);
+        let logs = get_logs_with_timeout(filter, 0, 100, provider.clone(), TEST_LOG_REQUEST_CONFIG)
+            .await?;
+
+        assert_eq!(logs.len(), 2);
+        assert_eq!(
+            provider.as_ref().as_ref().requests.load(Ordering::SeqCst),
+            2
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_all_pools_from_logs_reports_failed_ranges() -> eyre::Result<()> {
+        let factory_address = H160::from_low_u64_be(100);
+        let factory = Factory::UniswapV2Factory(UniswapV2Factory::new(factory_address, 0, 300));
+
+        let provider = Arc::new(Provider::new(StallingProvider {
+            stalled_from_blocks: vec![250],
+            logs: pair_created_logs(factory_address, &[10, 300, 600]),
+            ..Default::default()
+        }));
+
+        match factory
+            .get_all_pools_from_logs_with_config(
+                0,
+                1000,
+                250,
+                provider.clone(),
+                TEST_LOG_REQUEST_CONFIG,
+            )
+            .await
+        {
+            Err(AMMError::BatchRequestFailed(failed_ranges)) => {
+                assert_eq!(failed_ranges, vec![(250, 499)]);
+            }
+            _ => panic!("Expected the stalled block range to fail"),
+        }
+
+        //4 ranges, with the stalled range requested once and retried twice
+        assert_eq!(
+            provider.as_ref().as_ref().requests.load(Ordering::SeqCst),
+            6
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_all_pools_from_logs_shrinks_block_range() -> eyre::Result<()> {
+        let factory_address = H160::from_low_u64_be(100);
+        let factory = Factory::UniswapV2Factory(UniswapV2Factory::new(factory_address, 0, 300));
+
+        let logs = (1..=10)
+            .map(|i| Log {
+                address: factory_address,
+                topics: vec![
+                    PAIR_CREATED_EVENT_SIGNATURE,
+                    H256::from(H160::from_low_u64_be(i * 10)),
+                    H256::from(H160::from_low_u64_be(i * 10 + 1)),
+                ],
+                data: ethers::abi::encode(&[
+                    Token::Address(H160::from_low_u64_be(i)),
+                    Token::Uint(U256::from(i)),
+                ])
+                .into(),
+                block_number: Some(U64::from(i * 97)),
+                ..Default::default()
+            })
+            .collect();
+
+        let middleware = Arc::new(Provider::new(RangeLimitedProvider {
+            max_block_span: 100,
+            logs,
+        }));
+
+        let amms = factory
+            .get_all_pools_from_logs(0, 1000, 1000, middleware)
+            .await?;
+
+        let mut addresses: Vec<H160> = amms.iter().map(|amm| amm.address()).collect();
+        addresses.sort();
+
+        assert_eq!(
+            addresses,
+            (1..=10).map(H160::from_low_u64_be).collect::<Vec<H160>>()
+        );
+
+        Ok(())
+    }
+}
@@ -3,14 +3,22 @@ pub mod factory;
 pub mod uniswap_v2;
 pub mod uniswap_v3;
 
-use std::sync::Arc;
+use std::{collections::HashMap, fmt, sync::Arc};
 
 use async_trait::async_trait;
 use ethers::{
     providers::Middleware,
-    types::{Log, H160, H256, U256},
+    types::{Log, H160, H256, U256, U512},
 };
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{
+        self,
+        value::{MapAccessDeserializer, MapDeserializer},
+        MapAccess, Visitor,
+    },
+    Deserialize, Deserializer, Serialize,
+};
+use serde_json::value::RawValue;
 
 use crate::errors::{AMMError, ArithmeticError, EventLogError, SwapSimulationError};
 
@@ -30,9 +38,52 @@ pub trait AutomatedMarketMaker {
     /// Returns a vector of tokens in the AMM.
     fn tokens(&self) -> Vec<H160>;
 
+    /// Returns the reserves of the AMM, in the same order as `tokens`.
+    fn reserves(&self) -> Vec<u128>;
+
+    /// Returns the reserve of `token` in the AMM, or 0 if `token` is not in the AMM.
+    fn get_reserve(&self, token: H160) -> u128 {
+        self.tokens()
+            .iter()
+            .position(|t| t == &token)
+            .and_then(|i| self.reserves().get(i).copied())
+            .unwrap_or(0)
+    }
+
+    /// Returns the pair of tokens in the AMM, or `None` if the AMM does not hold exactly two tokens.
+    fn token_pair(&self) -> Option<TokenPair> {
+        match self.tokens().as_slice() {
+            [token_a, token_b] => Some(TokenPair::new(*token_a, *token_b)),
+            _ => None,
+        }
+    }
+
     /// Calculates a f64 representation of base token price in the AMM.
     fn calculate_price(&self, base_token: H160) -> Result<f64, ArithmeticError>;
 
+    /// Returns the total value locked in the AMM, denominated in whole units of `base_token`.
+    ///
+    /// Returns `ArithmeticError::InvalidToken` if `base_token` is not in the AMM.
+    fn total_value_locked(&self, base_token: H160) -> Result<f64, ArithmeticError>;
+
+    /// Returns the swap fee of the AMM in basis points, ie. 30 for a 0.3% fee.
+    fn fee_bps(&self) -> u32;
+
+    /// Returns the swap fee of the AMM as a fraction of the amount in, ie. 0.003 for a 0.3% fee.
+    fn fee_fraction(&self) -> f64 {
+        self.fee_bps() as f64 / 10000.0
+    }
+
+    /// Returns whether the price of `base_token` is within `min_price` and `max_price`, inclusive.
+    ///
+    /// Returns `false` if the price cannot be calculated.
+    fn is_price_within_range(&self, base_token: H160, min_price: f64, max_price: f64) -> bool {
+        match self.calculate_price(base_token) {
+            Ok(price) => price >= min_price && price <= max_price,
+            Err(_) => false,
+        }
+    }
+
     /// Updates the AMM data from a log.
     fn sync_from_log(&mut self, log: Log) -> Result<(), EventLogError>;
 
@@ -43,6 +94,11 @@ pub trait AutomatedMarketMaker {
         middleware: Arc<M>,
     ) -> Result<(), AMMError<M>>;
 
+    /// Returns whether the AMM data can be populated via `populate_data`.
+    fn supports_populate_data(&self) -> bool {
+        true
+    }
+
     /// Locally simulates a swap in the AMM.
     ///
     /// Returns the amount received for `amount_in` of `token_in`.
@@ -57,17 +113,245 @@ pub trait AutomatedMarketMaker {
         amount_in: U256,
     ) -> Result<U256, SwapSimulationError>;
 
+    /// Returns whether the AMM supports swapping `token_in` for `token_out`.
+    ///
+    /// Defaults to both tokens being in the AMM and differing. Routers should check it before simulating a hop.
+    fn can_swap(&self, token_in: H160, token_out: H160) -> bool {
+        let tokens = self.tokens();
+
+        token_in != token_out && tokens.contains(&token_in) && tokens.contains(&token_out)
+    }
+
+    /// Returns whether swapping `amount_in` of `token_in` yields at least `min_out`, without mutating the AMM.
+    fn can_fill(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        min_out: U256,
+    ) -> Result<bool, SwapSimulationError> {
+        Ok(self.simulate_swap(token_in, amount_in)? >= min_out)
+    }
+
+    /// Locally simulates a swap in the AMM after deducting a router fee of `router_fee_bps` basis points from `amount_in`.
+    ///
+    /// Models aggregators charging a fee on top of the AMM fee. Router fees above 100% are capped.
+    fn simulate_swap_with_router_fee(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        router_fee_bps: u32,
+    ) -> Result<U256, SwapSimulationError> {
+        let router_fee = amount_in * U256::from(router_fee_bps.min(10000)) / U256::from(10000);
+
+        self.simulate_swap(token_in, amount_in - router_fee)
+    }
+
     /// Returns the token out of the AMM for a given `token_in`.
+    ///
+    /// Assumes the AMM holds two tokens, see `tokens_out` for AMMs holding more.
     fn get_token_out(&self, token_in: H160) -> H160;
+
+    /// Returns all tokens that can be received for a given `token_in`.
+    ///
+    /// For two token AMMs this is the single counterpart of `token_in`. Returns an empty vector if `token_in` is not in the AMM.
+    fn tokens_out(&self, token_in: H160) -> Vec<H160> {
+        let tokens = self.tokens();
+
+        if !tokens.contains(&token_in) {
+            return vec![];
+        }
+
+        tokens
+            .into_iter()
+            .filter(|token| *token != token_in)
+            .collect()
+    }
+
+    /// Returns a snapshot of the AMM state mutated by swaps.
+    ///
+    /// Together with `restore_state`, allows simulating swaps via `simulate_swap_mut` and rolling them back.
+    fn state_snapshot(&self) -> AmmState;
+
+    /// Restores the AMM state from a snapshot taken with `state_snapshot`.
+    ///
+    /// Snapshots of a different AMM variant are ignored.
+    fn restore_state(&mut self, state: AmmState);
+
+    /// Returns any data quality issues found in the AMM.
+    ///
+    /// Useful to validate AMMs loaded from untrusted sources before simulating swaps. Defaults to reporting no issues.
+    fn sanity_check(&self) -> Vec<SanityIssue> {
+        vec![]
+    }
+
+    /// Returns an estimate of the memory used by the AMM in bytes, including its heap allocations.
+    ///
+    /// Defaults to the size of the AMM itself, AMMs holding heap allocated data should add it.
+    fn memory_size_bytes(&self) -> usize {
+        std::mem::size_of_val(self)
+    }
+
+    /// Serializes the AMM to a JSON string.
+    ///
+    /// For the `AMM` enum, the variant name is written to a `type` field next to the AMM data, ie.
+    /// `{"type": "UniswapV2Pool", "address": ...}`, which allows `amm_from_json` to deserialize the string back into the
+    /// correct variant.
+    fn to_json(&self) -> Result<String, serde_json::Error>
+    where
+        Self: Serialize,
+    {
+        serde_json::to_string(self)
+    }
+
+    /// Returns the loss in basis points from swapping `amount` of `token` and immediately swapping the output back.
+    ///
+    /// Captures the fee paid on both swaps as well as the slippage. The loss is rounded up.
+    fn round_trip_loss_bps(&self, token: H160, amount: U256) -> Result<u32, SwapSimulationError>
+    where
+        Self: Clone + Sized,
+    {
+        if amount.is_zero() {
+            return Err(SwapSimulationError::InsufficientLiquidity);
+        }
+
+        let mut amm = self.clone();
+        let amount_out = amm.simulate_swap_mut(token, amount)?;
+        let amount_back = amm.simulate_swap_mut(amm.get_token_out(token), amount_out)?;
+
+        if amount_back >= amount {
+            return Ok(0);
+        }
+
+        //Computed in U512 so that amounts close to U256::MAX do not overflow
+        let loss = amount - amount_back;
+        let loss_bps =
+            (loss.full_mul(U256::from(10000)) + U512::from(amount) - 1) / U512::from(amount);
+
+        Ok(loss_bps.as_u32())
+    }
+}
+
+/// The AMM state mutated by swaps, captured by `AutomatedMarketMaker::state_snapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AmmState {
+    UniswapV2Pool {
+        reserve_0: u128,
+        reserve_1: u128,
+    },
+    UniswapV3Pool {
+        liquidity: u128,
+        sqrt_price: U256,
+        tick: i32,
+    },
+    ERC4626Vault {
+        vault_reserve: U256,
+        asset_reserve: U256,
+    },
+}
+
+/// The two tokens of an AMM, in the order returned by `AutomatedMarketMaker::tokens`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TokenPair(pub H160, pub H160);
+
+impl TokenPair {
+    pub fn new(token_a: H160, token_b: H160) -> Self {
+        TokenPair(token_a, token_b)
+    }
+}
+
+/// Tokens with more decimals than this are reported as unusual by `AutomatedMarketMaker::sanity_check`.
+pub const MAX_TOKEN_DECIMALS: u8 = 36;
+
+/// A data quality issue reported by `AutomatedMarketMaker::sanity_check`.
+///
+/// Token symbols are not tracked by the AMMs in this crate, so symbols are not checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SanityIssue {
+    /// Reserves are zero or exceed what the AMM can hold on chain.
+    ReservesOutOfRange,
+    /// A token has more than `MAX_TOKEN_DECIMALS` decimals.
+    DecimalsUnusual(u8),
+    /// The AMM address is the same as one of its tokens.
+    AddressMismatch,
+    /// The fee, in the AMM's own fee units, is above the largest fee the AMM can charge.
+    FeeOutOfRange(u32),
+    /// Both tokens of the AMM have the same address.
+    TokensSameAddress,
 }
 
 macro_rules! amm {
     ($($pool_type:ident),+ $(,)?) => {
-        #[derive(Debug, Clone, Serialize, Deserialize)]
+        #[derive(Debug, Clone, Serialize)]
+        #[serde(tag = "type")]
         pub enum AMM {
             $($pool_type($pool_type),)+
         }
 
+        impl<'de> Deserialize<'de> for AMM {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserializer.deserialize_map(AmmVisitor)
+            }
+        }
+
+        //Reads the `type` tag and deserializes the remaining fields in place when the tag is the first field. Serde's derived
+        //internally tagged representation buffers the fields first, which loses the precision of u128 reserves above u64::MAX.
+        //When the tag comes later, e.g. in JSON with sorted keys, the fields are buffered as raw JSON until the tag is found.
+        //The externally tagged representation written by earlier versions is accepted as well, so older checkpoints still load.
+        struct AmmVisitor;
+
+        impl<'de> Visitor<'de> for AmmVisitor {
+            type Value = AMM;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an AMM with a `type` tag")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<AMM, A::Error> {
+                const VARIANTS: &[&str] = &[$(stringify!($pool_type),)+];
+
+                let key = map
+                    .next_key::<String>()?
+                    .ok_or_else(|| de::Error::missing_field("type"))?;
+
+                if key == "type" {
+                    let variant = map.next_value::<String>()?;
+                    let fields = MapAccessDeserializer::new(map);
+
+                    match variant.as_str() {
+                        $(stringify!($pool_type) => $pool_type::deserialize(fields).map(AMM::$pool_type),)+
+                        _ => Err(de::Error::unknown_variant(&variant, VARIANTS)),
+                    }
+                } else {
+                    //A variant name as the first field is the externally tagged representation of earlier versions
+                    let mut fields: Vec<(String, Box<RawValue>)> = match key.as_str() {
+                        $(stringify!($pool_type) => return map.next_value().map(AMM::$pool_type),)+
+                        _ => vec![(key, map.next_value()?)],
+                    };
+                    let mut variant = None;
+
+                    while let Some(key) = map.next_key::<String>()? {
+                        if key == "type" {
+                            variant = Some(map.next_value::<String>()?);
+                        } else {
+                            fields.push((key, map.next_value()?));
+                        }
+                    }
+
+                    let variant = variant.ok_or_else(|| de::Error::missing_field("type"))?;
+                    let fields = MapDeserializer::<_, serde_json::Error>::new(
+                        fields.iter().map(|(key, value)| (key.as_str(), &**value)),
+                    );
+
+                    match variant.as_str() {
+                        $(stringify!($pool_type) => $pool_type::deserialize(fields)
+                            .map(AMM::$pool_type)
+                            .map_err(de::Error::custom),)+
+                        _ => Err(de::Error::unknown_variant(&variant, VARIANTS)),
+                    }
+                }
+            }
+        }
+
         #[async_trait]
         impl AutomatedMarketMaker for AMM {
             fn address(&self) -> H160 {
@@ -112,25 +396,936 @@ macro_rules! amm {
                 }
             }
 
+            fn token_pair(&self) -> Option<TokenPair> {
+                match self {
+                    $(AMM::$pool_type(pool) => pool.token_pair(),)+
+                }
+            }
+
+            fn tokens_out(&self, token_in: H160) -> Vec<H160> {
+                match self {
+                    $(AMM::$pool_type(pool) => pool.tokens_out(token_in),)+
+                }
+            }
+
+            fn state_snapshot(&self) -> AmmState {
+                match self {
+                    $(AMM::$pool_type(pool) => pool.state_snapshot(),)+
+                }
+            }
+
+            fn restore_state(&mut self, state: AmmState) {
+                match self {
+                    $(AMM::$pool_type(pool) => pool.restore_state(state),)+
+                }
+            }
+
+            fn sanity_check(&self) -> Vec<SanityIssue> {
+                match self {
+                    $(AMM::$pool_type(pool) => pool.sanity_check(),)+
+                }
+            }
+
+            fn memory_size_bytes(&self) -> usize {
+                match self {
+                    //Include the enum tag and padding around the variant
+                    $(AMM::$pool_type(pool) => std::mem::size_of::<AMM>() - std::mem::size_of_val(pool) + pool.memory_size_bytes(),)+
+                }
+            }
+
+            fn fee_bps(&self) -> u32 {
+                match self {
+                    $(AMM::$pool_type(pool) => pool.fee_bps(),)+
+                }
+            }
+
+            fn total_value_locked(&self, base_token: H160) -> Result<f64, ArithmeticError> {
+                match self {
+                    $(AMM::$pool_type(pool) => pool.total_value_locked(base_token),)+
+                }
+            }
+
             async fn populate_data<M: Middleware>(&mut self, block_number: Option<u64>, middleware: Arc<M>) -> Result<(), AMMError<M>> {
                 match self {
                     $(AMM::$pool_type(pool) => pool.populate_data(block_number, middleware).await,)+
                 }
             }
 
+            fn supports_populate_data(&self) -> bool {
+                match self {
+                    $(AMM::$pool_type(pool) => pool.supports_populate_data(),)+
+                }
+            }
+
             fn tokens(&self) -> Vec<H160> {
                 match self {
                     $(AMM::$pool_type(pool) => pool.tokens(),)+
                 }
             }
 
+            fn can_swap(&self, token_in: H160, token_out: H160) -> bool {
+                match self {
+                    $(AMM::$pool_type(pool) => pool.can_swap(token_in, token_out),)+
+                }
+            }
+
+            fn reserves(&self) -> Vec<u128> {
+                match self {
+                    $(AMM::$pool_type(pool) => pool.reserves(),)+
+                }
+            }
+
+            fn get_reserve(&self, token: H160) -> u128 {
+                match self {
+                    $(AMM::$pool_type(pool) => pool.get_reserve(token),)+
+                }
+            }
+
             fn calculate_price(&self, base_token: H160) -> Result<f64, ArithmeticError> {
                 match self {
                     $(AMM::$pool_type(pool) => pool.calculate_price(base_token),)+
                 }
             }
         }
+
+        impl AMM {
+            /// Returns the name of the AMM variant.
+            pub fn variant_name(&self) -> &'static str {
+                match self {
+                    $(AMM::$pool_type(_) => stringify!($pool_type),)+
+                }
+            }
+        }
+
+        $(
+            impl TryFrom<AMM> for $pool_type {
+                type Error = ();
+
+                fn try_from(amm: AMM) -> Result<Self, Self::Error> {
+                    match amm {
+                        AMM::$pool_type(pool) => Ok(pool),
+                        _ => Err(()),
+                    }
+                }
+            }
+
+            impl<'a> TryFrom<&'a AMM> for &'a $pool_type {
+                type Error = ();
+
+                fn try_from(amm: &'a AMM) -> Result<Self, Self::Error> {
+                    match amm {
+                        AMM::$pool_type(pool) => Ok(pool),
+                        _ => Err(()),
+                    }
+                }
+            }
+        )+
     };
 }
 
 amm!(UniswapV2Pool, UniswapV3Pool, ERC4626Vault);
+
+/// Returns the address of the AMM yielding the largest amount out for `amount_in` of `token_in`, along with that amount.
+///
+/// Only AMMs containing both `token_in` and `token_out` are considered. AMMs that fail to simulate the swap are skipped.
+pub fn best_execution<'a>(
+    amms: impl IntoIterator<Item = &'a AMM>,
+    token_in: H160,
+    token_out: H160,
+    amount_in: U256,
+) -> Option<(H160, U256)> {
+    let mut best: Option<(H160, U256)> = None;
+
+    for amm in amms {
+        if !amm.can_swap(token_in, token_out) {
+            continue;
+        }
+
+        if let Ok(amount_out) = amm.simulate_swap(token_in, amount_in) {
+            let is_better = match best {
+                Some((_, best_amount_out)) => amount_out > best_amount_out,
+                None => true,
+            };
+
+            if is_better {
+                best = Some((amm.address(), amount_out));
+            }
+        }
+    }
+
+    best
+}
+
+/// Returns the AMMs trading the tokens of `pair`, in either order.
+pub fn amm_by_token_pair<'a>(amms: &'a HashMap<H160, AMM>, pair: &TokenPair) -> Vec<&'a AMM> {
+    let reversed_pair = TokenPair::new(pair.1, pair.0);
+
+    amms.values()
+        .filter(|amm| {
+            amm.token_pair()
+                .is_some_and(|amm_pair| amm_pair == *pair || amm_pair == reversed_pair)
+        })
+        .collect()
+}
+
+/// Returns the AMM trading `pair` yielding the largest amount out for `amount_in` of the first token of the pair, along with that amount.
+///
+/// AMMs that fail to simulate the swap are skipped, see `best_execution`.
+pub fn best_pool_for_pair<'a>(
+    amms: &'a HashMap<H160, AMM>,
+    pair: &TokenPair,
+    amount_in: U256,
+) -> Option<(&'a AMM, U256)> {
+    let candidates = amm_by_token_pair(amms, pair);
+    let (address, amount_out) =
+        best_execution(candidates.iter().copied(), pair.0, pair.1, amount_in)?;
+
+    candidates
+        .into_iter()
+        .find(|amm| amm.address() == address)
+        .map(|amm| (amm, amount_out))
+}
+
+/// Groups references to the AMMs by their variant name.
+pub fn amm_group_by_type(amms: &[AMM]) -> HashMap<&'static str, Vec<&AMM>> {
+    let mut groups: HashMap<&'static str, Vec<&AMM>> = HashMap::new();
+
+    for amm in amms {
+        groups.entry(amm.variant_name()).or_default().push(amm);
+    }
+
+    groups
+}
+
+/// Partitions the AMMs by their variant name.
+pub fn amm_partition_by_type(amms: Vec<AMM>) -> HashMap<&'static str, Vec<AMM>> {
+    let mut partitions: HashMap<&'static str, Vec<AMM>> = HashMap::new();
+
+    for amm in amms {
+        partitions.entry(amm.variant_name()).or_default().push(amm);
+    }
+
+    partitions
+}
+
+/// Deserializes an `AMM` from a JSON string produced by `AMM::to_json`, dispatching on its `type` tag.
+///
+/// The `type` tag may appear at any position, e.g. in JSON written with sorted keys. JSON wrapping the AMM data in the
+/// variant name, as written by earlier versions, is accepted as well.
+pub fn amm_from_json(s: &str) -> Result<AMM, serde_json::Error> {
+    serde_json::from_str(s)
+}
+
+/// Collapses AMMs sharing an address into a single entry and normalizes token ordering.
+///
+/// AMMs do not record when they were last synced, so later entries are treated as fresher and replace earlier entries with the same address.
+/// The position of the first entry for each address is preserved. Uniswap V2 pools are normalized so that `token_a` is the lower address, matching `token0` on-chain,
+/// swapping their decimals, reserves and tracked reserve updates along with the tokens.
+pub fn dedup_amms(amms: Vec<AMM>) -> Vec<AMM> {
+    let mut positions: HashMap<H160, usize> = HashMap::new();
+    let mut deduped_amms: Vec<AMM> = vec![];
+
+    for mut amm in amms {
+        if let AMM::UniswapV2Pool(pool) = &mut amm {
+            if pool.token_a > pool.token_b {
+                std::mem::swap(&mut pool.token_a, &mut pool.token_b);
+                std::mem::swap(&mut pool.token_a_decimals, &mut pool.token_b_decimals);
+                std::mem::swap(&mut pool.reserve_0, &mut pool.reserve_1);
+
+                if let Some(tracker) = pool.reserve_tracker.as_mut() {
+                    for (_, reserve_0, reserve_1) in tracker.updates.iter_mut() {
+                        std::mem::swap(reserve_0, reserve_1);
+                    }
+                }
+            }
+        }
+
+        match positions.get(&amm.address()) {
+            Some(position) => deduped_amms[*position] = amm,
+            None => {
+                positions.insert(amm.address(), deduped_amms.len());
+                deduped_amms.push(amm);
+            }
+        }
+    }
+
+    deduped_amms
+}
+
+impl AMM {
+    /// Calculates the price of each base token in the AMM.
+    ///
+    /// Returns an `ArithmeticError::InvalidToken` entry for base tokens that are not in the AMM.
+    pub fn prices_for(&self, base_tokens: &[H160]) -> Vec<Result<f64, ArithmeticError>> {
+        let tokens = self.tokens();
+
+        base_tokens
+            .iter()
+            .map(|base_token| {
+                if tokens.contains(base_token) {
+                    self.calculate_price(*base_token)
+                } else {
+                    Err(ArithmeticError::InvalidToken(*base_token))
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, mem::size_of};
+
+    use ethers::types::{H160, U256};
+
+    use crate::errors::ArithmeticError;
+
+    use super::{
+        amm_by_token_pair, amm_from_json, amm_group_by_type, amm_partition_by_type, best_execution,
+        best_pool_for_pair, dedup_amms,
+        erc_4626::ERC4626Vault,
+        uniswap_v2::{ReserveTracker, UniswapV2Pool},
+        uniswap_v3::{Info, UniswapV3Pool},
+        AmmState, AutomatedMarketMaker, TokenPair, AMM,
+    };
+
+    #[test]
+    fn test_get_reserve() {
+        let vault = AMM::ERC4626Vault(ERC4626Vault {
+            vault_token: H160::from_low_u64_be(1),
+            asset_token: H160::from_low_u64_be(2),
+            vault_reserve: U256::from(100),
+            asset_reserve: U256::MAX,
+            ..Default::default()
+        });
+
+        assert_eq!(vault.reserves(), vec![100, u128::MAX]);
+        assert_eq!(vault.get_reserve(H160::from_low_u64_be(1)), 100);
+        assert_eq!(vault.get_reserve(H160::from_low_u64_be(2)), u128::MAX);
+        assert_eq!(vault.get_reserve(H160::from_low_u64_be(3)), 0);
+
+        let pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 300,
+            reserve_1: 400,
+            ..Default::default()
+        });
+
+        assert_eq!(pool.get_reserve(H160::from_low_u64_be(2)), 400);
+        assert_eq!(pool.get_reserve(H160::from_low_u64_be(3)), 0);
+    }
+
+    #[test]
+    fn test_simulate_swap_with_router_fee() {
+        let token_in = H160::from_low_u64_be(1);
+        let pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            token_a: token_in,
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1_000_000_000,
+            reserve_1: 1_000_000_000,
+            fee: 300,
+            ..Default::default()
+        });
+        let amount_in = U256::from(1_000_000);
+
+        assert_eq!(
+            pool.simulate_swap_with_router_fee(token_in, amount_in, 0)
+                .unwrap(),
+            pool.simulate_swap(token_in, amount_in).unwrap()
+        );
+        assert_eq!(
+            pool.simulate_swap_with_router_fee(token_in, amount_in, 50)
+                .unwrap(),
+            pool.simulate_swap(token_in, U256::from(995_000)).unwrap()
+        );
+        assert!(pool
+            .simulate_swap_with_router_fee(token_in, amount_in, 10000)
+            .is_err());
+    }
+
+    #[test]
+    fn test_can_swap() {
+        let weth = H160::from_low_u64_be(1);
+        let usdc = H160::from_low_u64_be(2);
+        let pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            token_a: weth,
+            token_b: usdc,
+            ..Default::default()
+        });
+
+        assert!(pool.can_swap(weth, usdc));
+        assert!(pool.can_swap(usdc, weth));
+        assert!(!pool.can_swap(weth, weth));
+        assert!(!pool.can_swap(weth, H160::from_low_u64_be(3)));
+        assert!(!pool.can_swap(H160::from_low_u64_be(3), usdc));
+    }
+
+    #[test]
+    fn test_can_fill() {
+        let token_in = H160::from_low_u64_be(1);
+        let token_out = H160::from_low_u64_be(2);
+        let shallow_pool = AMM::UniswapV2Pool(UniswapV2Pool::test_pool(
+            10, token_in, token_out, 10_000_000, 10_000_000,
+        ));
+        let deep_pool = AMM::UniswapV2Pool(UniswapV2Pool::test_pool(
+            11,
+            token_in,
+            token_out,
+            1_000_000_000_000,
+            1_000_000_000_000,
+        ));
+        let empty_pool =
+            AMM::UniswapV2Pool(UniswapV2Pool::test_pool(12, token_in, token_out, 0, 0));
+        let amount_in = U256::from(1_000_000);
+        let min_out = U256::from(990_000);
+
+        assert!(!shallow_pool.can_fill(token_in, amount_in, min_out).unwrap());
+        assert!(deep_pool.can_fill(token_in, amount_in, min_out).unwrap());
+        assert!(empty_pool.can_fill(token_in, amount_in, min_out).is_err());
+    }
+
+    #[test]
+    fn test_fee_bps() {
+        let v2_pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            fee: 300,
+            ..Default::default()
+        });
+        let v3_pool = AMM::UniswapV3Pool(UniswapV3Pool {
+            fee: 500,
+            ..Default::default()
+        });
+        let vault = AMM::ERC4626Vault(ERC4626Vault {
+            deposit_fee: 10,
+            withdraw_fee: 25,
+            ..Default::default()
+        });
+
+        assert_eq!(v2_pool.fee_bps(), 30);
+        assert_eq!(v2_pool.fee_fraction(), 0.003);
+        assert_eq!(v3_pool.fee_bps(), 5);
+        assert_eq!(v3_pool.fee_fraction(), 0.0005);
+        assert_eq!(vault.fee_bps(), 25);
+    }
+
+    #[test]
+    fn test_memory_size_bytes() {
+        let mut ticks = HashMap::with_capacity(1000);
+        let mut tick_bitmap = HashMap::with_capacity(100);
+        for tick in 0..1000 {
+            ticks.insert(tick, Info::default());
+        }
+        for word in 0..100 {
+            tick_bitmap.insert(word, U256::MAX);
+        }
+
+        let v3_pool = AMM::UniswapV3Pool(UniswapV3Pool {
+            ticks,
+            tick_bitmap,
+            ..Default::default()
+        });
+
+        let AMM::UniswapV3Pool(pool) = &v3_pool else {
+            unreachable!()
+        };
+        assert!(pool.ticks.capacity() >= 1000 && pool.tick_bitmap.capacity() >= 100);
+        assert_eq!(
+            v3_pool.memory_size_bytes(),
+            size_of::<AMM>()
+                + pool.ticks.capacity() * size_of::<(i32, Info)>()
+                + pool.tick_bitmap.capacity() * size_of::<(i16, U256)>()
+        );
+
+        let mut pool = UniswapV2Pool::default();
+        let untracked_size = AMM::UniswapV2Pool(pool.clone()).memory_size_bytes();
+        assert_eq!(untracked_size, size_of::<AMM>());
+
+        pool.track_reserves(100);
+        let tracked_size = AMM::UniswapV2Pool(pool).memory_size_bytes();
+        assert!(tracked_size >= untracked_size + 100 * size_of::<(u64, u128, u128)>());
+
+        let vault = AMM::ERC4626Vault(ERC4626Vault::default());
+        assert_eq!(vault.memory_size_bytes(), size_of::<AMM>());
+    }
+
+    #[test]
+    fn test_try_from_amm() {
+        let amm = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(1),
+            ..Default::default()
+        });
+
+        let pool: &UniswapV2Pool = (&amm).try_into().unwrap();
+        assert_eq!(pool.address, H160::from_low_u64_be(1));
+        assert!(<&ERC4626Vault>::try_from(&amm).is_err());
+
+        assert!(ERC4626Vault::try_from(amm.clone()).is_err());
+        let pool = UniswapV2Pool::try_from(amm).unwrap();
+        assert_eq!(pool.address, H160::from_low_u64_be(1));
+    }
+
+    #[test]
+    fn test_dedup_amms() {
+        let token = H160::from_low_u64_be;
+
+        let mut reserve_tracker = ReserveTracker::new(2);
+        reserve_tracker.record(15, 300, 1000);
+        let reversed_pool = UniswapV2Pool {
+            reserve_tracker: Some(reserve_tracker),
+            ..UniswapV2Pool::test_pool(12, token(6), token(5), 300, 1000)
+        };
+
+        let amms = dedup_amms(vec![
+            AMM::UniswapV2Pool(UniswapV2Pool::test_pool(10, token(1), token(2), 100, 1000)),
+            AMM::UniswapV2Pool(UniswapV2Pool::test_pool(11, token(3), token(4), 100, 1000)),
+            //Fresher state of pool 10
+            AMM::UniswapV2Pool(UniswapV2Pool::test_pool(10, token(1), token(2), 200, 1000)),
+            //Reversed token ordering
+            AMM::UniswapV2Pool(reversed_pool),
+        ]);
+
+        let pools: Vec<&UniswapV2Pool> = amms.iter().map(|amm| amm.try_into().unwrap()).collect();
+
+        assert_eq!(pools.len(), 3);
+        assert_eq!(pools[0].address, H160::from_low_u64_be(10));
+        assert_eq!(pools[0].reserve_0, 200);
+        assert_eq!(pools[1].address, H160::from_low_u64_be(11));
+        assert_eq!(pools[2].token_a, H160::from_low_u64_be(5));
+        assert_eq!(pools[2].token_b, H160::from_low_u64_be(6));
+        assert_eq!(pools[2].reserve_0, 1000);
+        assert_eq!(pools[2].reserve_1, 300);
+        assert_eq!(
+            pools[2].reserve_tracker.as_ref().unwrap().updates,
+            vec![(15, 1000, 300)]
+        );
+    }
+
+    #[test]
+    fn test_is_price_within_range() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+
+        let amm = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(10),
+            token_a,
+            token_a_decimals: 18,
+            token_b,
+            token_b_decimals: 6,
+            reserve_0: 10_000_000_000_000_000_000,
+            reserve_1: 700_000_000_000,
+            fee: 300,
+            ..Default::default()
+        });
+
+        //Token a is priced at 70,000 token b
+        assert!(amm.is_price_within_range(token_a, 60_000.0, 80_000.0));
+        assert!(!amm.is_price_within_range(token_a, 71_000.0, 80_000.0));
+        assert!(!amm.is_price_within_range(token_b, 60_000.0, 80_000.0));
+
+        //The range is inclusive
+        let price = amm.calculate_price(token_a).unwrap();
+        assert!(amm.is_price_within_range(token_a, price, price));
+        assert!(!amm.is_price_within_range(token_a, 80_000.0, 60_000.0));
+    }
+
+    #[test]
+    fn test_token_pair() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+
+        let pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            token_a,
+            token_b,
+            ..Default::default()
+        });
+        let vault = AMM::ERC4626Vault(ERC4626Vault {
+            vault_token: token_b,
+            asset_token: token_a,
+            ..Default::default()
+        });
+
+        assert_eq!(pool.token_pair(), Some(TokenPair::new(token_a, token_b)));
+        assert_eq!(vault.token_pair(), Some(TokenPair::new(token_b, token_a)));
+    }
+
+    #[test]
+    fn test_amm_by_token_pair() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let token_c = H160::from_low_u64_be(3);
+
+        let amms: HashMap<H160, AMM> = [
+            UniswapV2Pool::test_pool(10, token_a, token_b, 1_000_000_000, 1_000_000_000),
+            UniswapV2Pool::test_pool(11, token_a, token_b, 1_000_000_000, 1_100_000_000),
+            UniswapV2Pool::test_pool(12, token_b, token_a, 1_000_000_000, 1_000_000_000),
+            UniswapV2Pool::test_pool(13, token_a, token_c, 1_000_000_000, 1_000_000_000),
+        ]
+        .into_iter()
+        .map(|pool| (pool.address, AMM::UniswapV2Pool(pool)))
+        .collect();
+
+        let pair = TokenPair::new(token_a, token_b);
+        let mut addresses: Vec<H160> = amm_by_token_pair(&amms, &pair)
+            .iter()
+            .map(|amm| amm.address())
+            .collect();
+        addresses.sort();
+
+        assert_eq!(
+            addresses,
+            (10..=12).map(H160::from_low_u64_be).collect::<Vec<H160>>()
+        );
+
+        let amount_in = U256::from(1_000_000);
+        let (best_pool, amount_out) = best_pool_for_pair(&amms, &pair, amount_in).unwrap();
+
+        assert_eq!(best_pool.address(), H160::from_low_u64_be(11));
+        assert_eq!(
+            amount_out,
+            best_pool.simulate_swap(token_a, amount_in).unwrap()
+        );
+        assert!(best_pool_for_pair(&amms, &TokenPair::new(token_b, token_c), amount_in).is_none());
+    }
+
+    #[test]
+    fn test_tokens_out() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+
+        let amm = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(10),
+            token_a,
+            token_b,
+            ..Default::default()
+        });
+
+        assert_eq!(amm.tokens_out(token_a), vec![token_b]);
+        assert_eq!(amm.tokens_out(token_b), vec![token_a]);
+        assert_eq!(amm.tokens_out(token_a)[0], amm.get_token_out(token_a));
+        assert!(amm.tokens_out(H160::from_low_u64_be(3)).is_empty());
+    }
+
+    #[test]
+    fn test_restore_state() -> eyre::Result<()> {
+        let token_a = H160::from_low_u64_be(1);
+        let mut amm = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(10),
+            token_a,
+            token_a_decimals: 18,
+            token_b: H160::from_low_u64_be(2),
+            token_b_decimals: 18,
+            reserve_0: 1_000_000_000,
+            reserve_1: 2_000_000_000,
+            fee: 300,
+            ..Default::default()
+        });
+
+        let snapshot = amm.state_snapshot();
+        let amount_out = amm.simulate_swap(token_a, U256::from(1_000_000))?;
+
+        amm.simulate_swap_mut(token_a, U256::from(1_000_000))?;
+        assert_ne!(amm.state_snapshot(), snapshot);
+
+        amm.restore_state(snapshot.clone());
+        assert_eq!(amm.state_snapshot(), snapshot);
+        assert_eq!(
+            snapshot,
+            AmmState::UniswapV2Pool {
+                reserve_0: 1_000_000_000,
+                reserve_1: 2_000_000_000,
+            }
+        );
+        assert_eq!(
+            amm.simulate_swap(token_a, U256::from(1_000_000))?,
+            amount_out
+        );
+
+        //Snapshots of other variants are ignored
+        amm.restore_state(AmmState::ERC4626Vault {
+            vault_reserve: U256::zero(),
+            asset_reserve: U256::zero(),
+        });
+        assert_eq!(amm.state_snapshot(), snapshot);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prices_for() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let unrelated_token = H160::from_low_u64_be(3);
+
+        let amm = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(10),
+            token_a,
+            token_a_decimals: 18,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0: 1_000_000,
+            reserve_1: 2_000_000,
+            fee: 300,
+            ..Default::default()
+        });
+
+        let prices = amm.prices_for(&[token_a, token_b, unrelated_token]);
+
+        assert_eq!(prices.len(), 3);
+        assert_eq!(
+            *prices[0].as_ref().unwrap(),
+            amm.calculate_price(token_a).unwrap()
+        );
+        assert_eq!(
+            *prices[1].as_ref().unwrap(),
+            amm.calculate_price(token_b).unwrap()
+        );
+        assert!(matches!(
+            prices[2],
+            Err(ArithmeticError::InvalidToken(token)) if token == unrelated_token
+        ));
+    }
+
+    #[test]
+    fn test_best_execution() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+
+        let amms = vec![
+            AMM::UniswapV2Pool(UniswapV2Pool::test_pool(
+                10,
+                token_a,
+                token_b,
+                1_000_000_000,
+                1_000_000_000,
+            )),
+            //Same depth with a better price
+            AMM::UniswapV2Pool(UniswapV2Pool::test_pool(
+                11,
+                token_a,
+                token_b,
+                1_000_000_000,
+                1_100_000_000,
+            )),
+            //Same depth and price with a higher fee
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                fee: 3000,
+                ..UniswapV2Pool::test_pool(12, token_a, token_b, 1_000_000_000, 1_100_000_000)
+            }),
+        ];
+
+        let amount_in = U256::from(1_000_000);
+        let (best_pool, amount_out) = best_execution(&amms, token_a, token_b, amount_in).unwrap();
+
+        assert_eq!(best_pool, H160::from_low_u64_be(11));
+        assert_eq!(
+            amount_out,
+            amms[1].simulate_swap(token_a, amount_in).unwrap()
+        );
+
+        assert!(best_execution(&amms, token_a, H160::from_low_u64_be(3), amount_in).is_none());
+    }
+
+    #[test]
+    fn test_json_round_trip() -> eyre::Result<()> {
+        let pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(1),
+            token_a: H160::from_low_u64_be(2),
+            token_a_decimals: 6,
+            token_b: H160::from_low_u64_be(3),
+            token_b_decimals: 18,
+            reserve_0: 1000,
+            reserve_1: 2000,
+            fee: 300,
+            creation_block: 100,
+            k_last: U256::from(2_000_000),
+            ..Default::default()
+        });
+
+        let vault = AMM::ERC4626Vault(ERC4626Vault {
+            vault_token: H160::from_low_u64_be(4),
+            vault_token_decimals: 18,
+            asset_token: H160::from_low_u64_be(3),
+            asset_token_decimals: 18,
+            vault_reserve: U256::from(1000),
+            asset_reserve: U256::from(1100),
+            deposit_fee: 10,
+            withdraw_fee: 20,
+        });
+
+        match amm_from_json(&pool.to_json()?)? {
+            AMM::UniswapV2Pool(deserialized_pool) => {
+                assert_eq!(deserialized_pool.address, H160::from_low_u64_be(1));
+                assert_eq!(deserialized_pool.reserve_1, 2000);
+                assert_eq!(deserialized_pool.fee, 300);
+                assert_eq!(deserialized_pool.creation_block, 100);
+            }
+            _ => panic!("Unexpected AMM variant"),
+        }
+
+        match amm_from_json(&vault.to_json()?)? {
+            AMM::ERC4626Vault(deserialized_vault) => {
+                assert_eq!(deserialized_vault.vault_token, H160::from_low_u64_be(4));
+                assert_eq!(deserialized_vault.asset_reserve, U256::from(1100));
+                assert_eq!(deserialized_vault.withdraw_fee, 20);
+            }
+            _ => panic!("Unexpected AMM variant"),
+        }
+
+        let tagged: serde_json::Value = serde_json::from_str(&pool.to_json()?)?;
+        assert_eq!(tagged["type"], "UniswapV2Pool");
+        assert_eq!(
+            tagged["address"],
+            "0x0000000000000000000000000000000000000001"
+        );
+        assert!(tagged.get("UniswapV2Pool").is_none());
+
+        //Reserves above u64::MAX keep their precision
+        let deep_pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            reserve_0: u64::MAX as u128 * 1000 + 7,
+            ..Default::default()
+        });
+        match amm_from_json(&deep_pool.to_json()?)? {
+            AMM::UniswapV2Pool(deserialized_pool) => {
+                assert_eq!(deserialized_pool.reserve_0, u64::MAX as u128 * 1000 + 7)
+            }
+            _ => panic!("Unexpected AMM variant"),
+        }
+
+        //JSON wrapping the AMM data in the variant name, as written by earlier versions
+        let legacy_json = format!(
+            r#"{{"ERC4626Vault": {}}}"#,
+            serde_json::to_string(&ERC4626Vault {
+                vault_token: H160::from_low_u64_be(4),
+                ..Default::default()
+            })?
+        );
+        assert_eq!(
+            amm_from_json(&legacy_json)?.address(),
+            H160::from_low_u64_be(4)
+        );
+
+        assert!(amm_from_json(r#"{"type": "CurvePool"}"#).is_err());
+        assert!(amm_from_json(r#"{"type": 1}"#).is_err());
+        assert!(
+            amm_from_json(r#"{"address": "0x0000000000000000000000000000000000000001"}"#).is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_type_tag_position() -> eyre::Result<()> {
+        let pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(1),
+            reserve_0: u64::MAX as u128 * 1000 + 7,
+            fee: 300,
+            ..Default::default()
+        });
+        let vault = AMM::ERC4626Vault(ERC4626Vault {
+            vault_token: H160::from_low_u64_be(4),
+            withdraw_fee: 20,
+            ..Default::default()
+        });
+
+        //Move the tag to the end, keeping the reserves above u64::MAX as written
+        let tag = r#""type":"UniswapV2Pool","#;
+        let pool_json = pool.to_json()?;
+        assert!(pool_json.starts_with(&format!("{{{tag}")));
+        let tag_last = format!(
+            "{{{},{}}}",
+            &pool_json[tag.len() + 1..pool_json.len() - 1],
+            tag.trim_end_matches(',')
+        );
+        assert_eq!(amm_from_json(&tag_last)?.to_json()?, pool_json);
+
+        //Sorted keys, as written by `jq -S` or Python's `sort_keys=True`
+        let sorted_json = serde_json::to_string(&serde_json::from_str::<serde_json::Value>(
+            &vault.to_json()?,
+        )?)?;
+        assert!(!sorted_json.starts_with(r#"{"type""#));
+        assert_eq!(amm_from_json(&sorted_json)?.to_json()?, vault.to_json()?);
+
+        assert!(
+            amm_from_json(r#"{"address":"0x0000000000000000000000000000000000000001"}"#).is_err()
+        );
+        assert!(amm_from_json(
+            r#"{"address":"0x0000000000000000000000000000000000000001","type":"Unknown"}"#
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_round_trip_legacy_representation() -> eyre::Result<()> {
+        let pool = UniswapV2Pool {
+            address: H160::from_low_u64_be(1),
+            token_a: H160::from_low_u64_be(2),
+            reserve_0: 1000,
+            fee: 300,
+            ..Default::default()
+        };
+        let vault = ERC4626Vault {
+            vault_token: H160::from_low_u64_be(4),
+            withdraw_fee: 20,
+            ..Default::default()
+        };
+
+        let legacy_jsons = [
+            format!(r#"{{"UniswapV2Pool": {}}}"#, serde_json::to_string(&pool)?),
+            format!(r#"{{"ERC4626Vault": {}}}"#, serde_json::to_string(&vault)?),
+        ];
+
+        for (amm, legacy_json) in [AMM::UniswapV2Pool(pool), AMM::ERC4626Vault(vault)]
+            .into_iter()
+            .zip(legacy_jsons)
+        {
+            assert_eq!(amm_from_json(&amm.to_json()?)?.to_json()?, amm.to_json()?);
+
+            //Older files are rewritten with the `type` tag once loaded
+            assert_eq!(amm_from_json(&legacy_json)?.to_json()?, amm.to_json()?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_by_type() {
+        let amms = vec![
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(1),
+                ..Default::default()
+            }),
+            AMM::ERC4626Vault(ERC4626Vault {
+                vault_token: H160::from_low_u64_be(2),
+                ..Default::default()
+            }),
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(3),
+                ..Default::default()
+            }),
+        ];
+
+        assert_eq!(amms[0].variant_name(), "UniswapV2Pool");
+        assert_eq!(amms[1].variant_name(), "ERC4626Vault");
+
+        let groups = amm_group_by_type(&amms);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups["UniswapV2Pool"].len(), 2);
+        assert_eq!(groups["ERC4626Vault"].len(), 1);
+
+        let partitions = amm_partition_by_type(amms);
+        assert_eq!(partitions.len(), 2);
+        assert_eq!(
+            partitions["UniswapV2Pool"]
+                .iter()
+                .map(|amm| amm.address())
+                .collect::<Vec<H160>>(),
+            vec![H160::from_low_u64_be(1), H160::from_low_u64_be(3)]
+        );
+        assert_eq!(
+            partitions["ERC4626Vault"][0].address(),
+            H160::from_low_u64_be(2)
+        );
+    }
+}
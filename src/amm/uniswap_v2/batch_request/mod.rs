@@ -12,7 +12,7 @@ use crate::{
 
 use ethers::prelude::abigen;
 
-use super::UniswapV2Pool;
+use super::{UniswapV2Pool, U112_MAX};
 
 abigen!(
 
@@ -23,10 +23,15 @@ abigen!(
         "src/amm/uniswap_v2/batch_request/GetUniswapV2PoolDataBatchRequestABI.json";
 );
 
+//Returns `None` if the tokens are malformed or the reserves exceed uint112, see `reserves_overflow`
 fn populate_pool_data_from_tokens(
     mut pool: UniswapV2Pool,
     tokens: Vec<Token>,
 ) -> Option<UniswapV2Pool> {
+    if reserves_overflow(&tokens) {
+        return None;
+    }
+
     pool.token_a = tokens[0].to_owned().into_address()?;
     pool.token_a_decimals = tokens[1].to_owned().into_uint()?.as_u32() as u8;
     pool.token_b = tokens[2].to_owned().into_address()?;
@@ -37,6 +42,16 @@ fn populate_pool_data_from_tokens(
     Some(pool)
 }
 
+//Reserves are stored as uint112 on chain, larger values indicate a non standard pair
+fn reserves_overflow(tokens: &[Token]) -> bool {
+    tokens[4..6].iter().any(|token| {
+        token
+            .to_owned()
+            .into_uint()
+            .is_some_and(|reserve| reserve > U256::from(U112_MAX))
+    })
+}
+
 pub async fn get_pairs_batch_request<M: Middleware>(
     factory: H160,
     from: U256,
@@ -114,7 +129,10 @@ pub async fn get_amm_data_batch_request<M: Middleware>(
                                 .get_mut(pool_idx)
                                 .expect("Pool idx should be in bounds")
                             {
-                                if let Some(pool) = populate_pool_data_from_tokens(
+                                //Pools with oversized reserves are left unpopulated and filtered as empty
+                                if reserves_overflow(&pool_data) {
+                                    tracing::warn!(address = ?uniswap_v2_pool.address, "Reserves exceed uint112, skipping pool");
+                                } else if let Some(pool) = populate_pool_data_from_tokens(
                                     uniswap_v2_pool.to_owned(),
                                     pool_data,
                                 ) {
@@ -162,6 +180,10 @@ pub async fn get_v2_pool_data_batch_request<M: Middleware>(
                     .into_tuple()
                     .ok_or(AMMError::BatchRequestError(pool.address))?;
 
+                if reserves_overflow(&pool_data) {
+                    return Err(AMMError::ReservesOverflow(pool.address));
+                }
+
                 *pool = populate_pool_data_from_tokens(pool.to_owned(), pool_data)
                     .ok_or(AMMError::BatchRequestError(pool.address))?;
             }
@@ -0,0 +1,12 @@
+use ruint::Uint;
+
+/// LP tokens permanently locked to the zero address when a pair is first minted.
+pub const MINIMUM_LIQUIDITY: u128 = 1000;
+/// Storage slot of `reserve0`, `reserve1` and `blockTimestampLast` in the canonical UniswapV2Pair.
+pub const RESERVES_STORAGE_SLOT: u64 = 8;
+/// Largest value of the uint112 reserves in a UniswapV2Pair.
+pub const U112_MAX: u128 = (1u128 << 112) - 1;
+/// Largest u128 value.
+pub const U128_MAX: u128 = u128::MAX;
+/// Largest u128 value as a 256 bit integer.
+pub const U256_U128_MAX: Uint<256, 4> = Uint::<256, 4>::from_limbs([u64::MAX, u64::MAX, 0, 0]);
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use async_trait::async_trait;
 use ethers::{
@@ -10,12 +10,13 @@ use ethers::{
 
 use crate::{
     amm::{factory::AutomatedMarketMakerFactory, AMM},
-    errors::AMMError,
+    errors::{AMMError, EventLogError},
 };
 use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
 use tracing::instrument;
 
-use super::{batch_request, UniswapV2Pool};
+use super::{batch_request, FeeModel, UniswapV2Pool};
 
 use ethers::prelude::abigen;
 
@@ -35,11 +36,64 @@ pub const PAIR_CREATED_EVENT_SIGNATURE: H256 = H256([
     131, 85, 205, 222, 253, 227, 26, 250, 40, 208, 233,
 ]);
 
+/// Uniswap V2 factory on Ethereum mainnet, `0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f`.
+pub const UNISWAP_V2_FACTORY_MAINNET: H160 = H160([
+    92, 105, 190, 231, 1, 239, 129, 74, 43, 106, 62, 221, 75, 22, 82, 203, 156, 197, 170, 111,
+]);
+pub const UNISWAP_V2_CREATION_BLOCK_MAINNET: u64 = 10000835;
+
+/// Uniswap V2 factory on Arbitrum, `0xf1D7CC64Fb4452F05c498126312eBE29f30Fbcf9`.
+pub const UNISWAP_V2_FACTORY_ARBITRUM: H160 = H160([
+    241, 215, 204, 100, 251, 68, 82, 240, 92, 73, 129, 38, 49, 46, 190, 41, 243, 15, 188, 249,
+]);
+pub const UNISWAP_V2_CREATION_BLOCK_ARBITRUM: u64 = 150442611;
+
+/// Uniswap V2 factory on Optimism, `0x0c3c1c532F1e39EdF36BE9Fe0bE1410313E074Bf`.
+pub const UNISWAP_V2_FACTORY_OPTIMISM: H160 = H160([
+    12, 60, 28, 83, 47, 30, 57, 237, 243, 107, 233, 254, 11, 225, 65, 3, 19, 224, 116, 191,
+]);
+pub const UNISWAP_V2_CREATION_BLOCK_OPTIMISM: u64 = 112197986;
+
+/// Uniswap V2 factory on Polygon, `0x9e5A52f57b3038F1B8EeE45F28b3C1967e22799C`.
+pub const UNISWAP_V2_FACTORY_POLYGON: H160 = H160([
+    158, 90, 82, 245, 123, 48, 56, 241, 184, 238, 228, 95, 40, 179, 193, 150, 126, 34, 121, 156,
+]);
+pub const UNISWAP_V2_CREATION_BLOCK_POLYGON: u64 = 49948178;
+
+/// Uniswap V2 factory on Base, `0x8909Dc15e40173Ff4699343b6eB8132c65e18eC6`.
+pub const UNISWAP_V2_FACTORY_BASE: H160 = H160([
+    137, 9, 220, 21, 228, 1, 115, 255, 70, 153, 52, 59, 110, 184, 19, 44, 101, 225, 142, 198,
+]);
+
+/// Sushiswap factory on Ethereum mainnet, `0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac`.
+pub const SUSHISWAP_FACTORY_MAINNET: H160 = H160([
+    192, 174, 228, 120, 227, 101, 142, 38, 16, 197, 247, 164, 162, 225, 119, 124, 233, 228, 242,
+    172,
+]);
+
+/// Known Uniswap V2 style factory deployments and the id of the chain they are deployed on.
+pub const KNOWN_FACTORY_CHAIN_IDS: [(H160, u64); 6] = [
+    //Uniswap V2
+    (UNISWAP_V2_FACTORY_MAINNET, 1),
+    (UNISWAP_V2_FACTORY_ARBITRUM, 42161),
+    (UNISWAP_V2_FACTORY_OPTIMISM, 10),
+    (UNISWAP_V2_FACTORY_POLYGON, 137),
+    (UNISWAP_V2_FACTORY_BASE, 8453),
+    //Sushiswap
+    (SUSHISWAP_FACTORY_MAINNET, 1),
+];
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct UniswapV2Factory {
     pub address: H160,
     pub creation_block: u64,
     pub fee: u32,
+    // Per pair fees keyed by the sorted token pair, populated by `fee_for_pair`
+    #[serde(skip)]
+    pub pair_fees: Arc<RwLock<HashMap<(H160, H160), u32>>>,
+    // Fee formula inherited by the pools of the factory, see `with_fee_model`. Custom models cannot be serialized
+    #[serde(default)]
+    pub fee_model: Option<FeeModel>,
 }
 
 impl UniswapV2Factory {
@@ -48,7 +102,121 @@ impl UniswapV2Factory {
             address,
             creation_block,
             fee,
+            pair_fees: Arc::new(RwLock::new(HashMap::new())),
+            fee_model: None,
+        }
+    }
+
+    /// Returns the Uniswap V2 factory deployed on Ethereum mainnet.
+    pub fn uniswap_v2_mainnet() -> UniswapV2Factory {
+        UniswapV2Factory::new(
+            UNISWAP_V2_FACTORY_MAINNET,
+            UNISWAP_V2_CREATION_BLOCK_MAINNET,
+            300,
+        )
+    }
+
+    /// Returns the Uniswap V2 factory deployed on Arbitrum.
+    pub fn uniswap_v2_arbitrum() -> UniswapV2Factory {
+        UniswapV2Factory::new(
+            UNISWAP_V2_FACTORY_ARBITRUM,
+            UNISWAP_V2_CREATION_BLOCK_ARBITRUM,
+            300,
+        )
+    }
+
+    /// Returns the Uniswap V2 factory deployed on Optimism.
+    pub fn uniswap_v2_optimism() -> UniswapV2Factory {
+        UniswapV2Factory::new(
+            UNISWAP_V2_FACTORY_OPTIMISM,
+            UNISWAP_V2_CREATION_BLOCK_OPTIMISM,
+            300,
+        )
+    }
+
+    /// Returns the Uniswap V2 factory deployed on Polygon.
+    pub fn uniswap_v2_polygon() -> UniswapV2Factory {
+        UniswapV2Factory::new(
+            UNISWAP_V2_FACTORY_POLYGON,
+            UNISWAP_V2_CREATION_BLOCK_POLYGON,
+            300,
+        )
+    }
+
+    /// Sets the fee formula used by the pools of the factory, for forks whose swap math deviates from Uniswap V2.
+    pub fn with_fee_model(mut self, fee_model: FeeModel) -> Self {
+        self.fee_model = Some(fee_model);
+        self
+    }
+
+    /// Returns the swap fee of the pair for `token_a` and `token_b`.
+    ///
+    /// Some forks configure fees per pair and expose them via `fee()` on the pair contract, see `UniswapV2Pool::get_pair_fee`.
+    /// If the pair does not implement `fee()`, the factory fee is returned. Fees reported by the pair are cached per pair.
+    pub async fn fee_for_pair<M: Middleware>(
+        &self,
+        token_a: H160,
+        token_b: H160,
+        middleware: Arc<M>,
+    ) -> Result<u32, AMMError<M>> {
+        let pair_key = pair_key(token_a, token_b);
+
+        if let Some(fee) = self.pair_fees.read().await.get(&pair_key) {
+            return Ok(*fee);
+        }
+
+        let factory = IUniswapV2Factory::new(self.address, middleware.clone());
+        let pair_address = factory.get_pair(token_a, token_b).call().await?;
+
+        if pair_address.is_zero() {
+            return Err(AMMError::PairDoesNotExistInDexes(token_a, token_b));
         }
+
+        //Standard V2 pairs do not implement `fee()`, fall back to the factory fee
+        let Some(fee) = UniswapV2Pool::get_pair_fee(pair_address, None, middleware).await? else {
+            return Ok(self.fee);
+        };
+
+        tracing::trace!(?pair_address, fee, "pair fee");
+
+        self.pair_fees.write().await.insert(pair_key, fee);
+
+        Ok(fee)
+    }
+
+    /// Sets the fee of each pool to the fee reported by `fee()` on the pair, for forks that configure fees per pair.
+    ///
+    /// Pairs of a factory share the same code, so once a pair does not implement `fee()` the remaining pools keep their
+    /// fee without querying their pair. Fees reported by the pair are cached per pair, see `fee_for_pair`.
+    pub async fn populate_pair_fees<M: Middleware>(
+        &self,
+        amms: &mut [AMM],
+        block_number: Option<u64>,
+        middleware: Arc<M>,
+    ) -> Result<(), AMMError<M>> {
+        for amm in amms.iter_mut() {
+            let AMM::UniswapV2Pool(pool) = amm else {
+                continue;
+            };
+
+            let pair_key = pair_key(pool.token_a, pool.token_b);
+
+            if let Some(fee) = self.pair_fees.read().await.get(&pair_key) {
+                pool.fee = *fee;
+                continue;
+            }
+
+            let Some(fee) =
+                UniswapV2Pool::get_pair_fee(pool.address, block_number, middleware.clone()).await?
+            else {
+                return Ok(());
+            };
+
+            pool.fee = fee;
+            self.pair_fees.write().await.insert(pair_key, fee);
+        }
+
+        Ok(())
     }
 
     pub async fn get_all_pairs_via_batched_calls<M: Middleware>(
@@ -94,6 +262,9 @@ impl UniswapV2Factory {
         for addr in pairs {
             let amm = UniswapV2Pool {
                 address: addr,
+                fee: self.fee,
+                fee_model: self.fee_model,
+                factory: self.address,
                 ..Default::default()
             };
 
@@ -119,26 +290,30 @@ impl AutomatedMarketMakerFactory for UniswapV2Factory {
         log: Log,
         middleware: Arc<M>,
     ) -> Result<AMM, AMMError<M>> {
+        let creation_block = log.block_number.unwrap_or_default().as_u64();
         let pair_created_event: PairCreatedFilter =
             PairCreatedFilter::decode_log(&RawLog::from(log))?;
-        Ok(AMM::UniswapV2Pool(
-            UniswapV2Pool::new_from_address(pair_created_event.pair, self.fee, middleware).await?,
-        ))
+
+        //Populating the pool replaces the factory fee with the pair fee, for forks with per pair fees
+        let mut pool =
+            UniswapV2Pool::new_from_address(pair_created_event.pair, self.fee, middleware).await?;
+        pool.creation_block = creation_block;
+        pool.factory = self.address;
+        pool.fee_model = self.fee_model;
+
+        Ok(AMM::UniswapV2Pool(pool))
     }
 
     fn new_empty_amm_from_log(&self, log: Log) -> Result<AMM, ethers::abi::Error> {
-        let pair_created_event = PairCreatedFilter::decode_log(&RawLog::from(log))?;
-
-        Ok(AMM::UniswapV2Pool(UniswapV2Pool {
-            address: pair_created_event.pair,
-            token_a: pair_created_event.token_0,
-            token_b: pair_created_event.token_1,
-            token_a_decimals: 0,
-            token_b_decimals: 0,
-            reserve_0: 0,
-            reserve_1: 0,
-            fee: 0,
-        }))
+        match UniswapV2Pool::from_log_and_fee(log, self.fee) {
+            Ok(mut pool) => {
+                pool.fee_model = self.fee_model;
+                pool.factory = self.address;
+                Ok(AMM::UniswapV2Pool(pool))
+            }
+            Err(EventLogError::EthABIError(err)) => Err(err),
+            Err(_) => Err(ethers::abi::Error::InvalidData),
+        }
     }
 
     #[instrument(skip(self, middleware) level = "debug")]
@@ -154,17 +329,35 @@ impl AutomatedMarketMakerFactory for UniswapV2Factory {
     async fn populate_amm_data<M: Middleware>(
         &self,
         amms: &mut [AMM],
-        _block_number: Option<u64>,
+        block_number: Option<u64>,
         middleware: Arc<M>,
     ) -> Result<(), AMMError<M>> {
         let step = 127; //Max batch size for call
         for amm_chunk in amms.chunks_mut(step) {
             batch_request::get_amm_data_batch_request(amm_chunk, middleware.clone()).await?;
         }
-        Ok(())
+
+        self.populate_pair_fees(amms, block_number, middleware)
+            .await
     }
 
     fn creation_block(&self) -> u64 {
         self.creation_block
     }
+
+    fn chain_id(&self) -> Option<u64> {
+        KNOWN_FACTORY_CHAIN_IDS
+            .iter()
+            .find(|(address, _)| *address == self.address)
+            .map(|(_, chain_id)| *chain_id)
+    }
+}
+
+//Returns the token pair sorted by address, the key of `UniswapV2Factory::pair_fees`
+fn pair_key(token_a: H160, token_b: H160) -> (H160, H160) {
+    if token_a < token_b {
+        (token_a, token_b)
+    } else {
+        (token_b, token_a)
+    }
 }
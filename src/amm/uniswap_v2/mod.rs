@@ -1,10 +1,14 @@
 pub mod batch_request;
+pub mod constant;
 pub mod factory;
 
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
 
 use crate::{
-    amm::AutomatedMarketMaker,
+    amm::{AmmState, AutomatedMarketMaker, SanityIssue, TokenPair, MAX_TOKEN_DECIMALS},
     errors::{AMMError, ArithmeticError, EventLogError, SwapSimulationError},
 };
 use async_trait::async_trait;
@@ -13,15 +17,21 @@ use ethers::{
     prelude::EthEvent,
     providers::Middleware,
     types::{Log, H160, H256, U256},
+    utils::to_checksum,
 };
 use num_bigfloat::BigFloat;
 use ruint::Uint;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use tracing::instrument;
 
 use ethers::prelude::abigen;
 
-use self::factory::PAIR_CREATED_EVENT_SIGNATURE;
+pub use self::constant::U112_MAX;
+use self::{
+    constant::{MINIMUM_LIQUIDITY, RESERVES_STORAGE_SLOT},
+    factory::PAIR_CREATED_EVENT_SIGNATURE,
+};
 
 abigen!(
     IUniswapV2Pair,
@@ -30,6 +40,8 @@ abigen!(
         function token0() external view returns (address)
         function token1() external view returns (address)
         function swap(uint256 amount0Out, uint256 amount1Out, address to, bytes calldata data);
+        function fee() external view returns (uint32)
+        function kLast() external view returns (uint256)
         event Sync(uint112 reserve0, uint112 reserve1)
     ]"#;
 
@@ -38,6 +50,13 @@ abigen!(
         function balanceOf(address account) external view returns (uint256)
         function decimals() external view returns (uint8)
     ]"#;
+
+    IUniswapV2Router,
+    r#"[
+        function getAmountsOut(uint256 amountIn, address[] calldata path) external view returns (uint256[] memory amounts)
+        function addLiquidity(address tokenA, address tokenB, uint256 amountADesired, uint256 amountBDesired, uint256 amountAMin, uint256 amountBMin, address to, uint256 deadline) external returns (uint256 amountA, uint256 amountB, uint256 liquidity)
+        function removeLiquidity(address tokenA, address tokenB, uint256 liquidity, uint256 amountAMin, uint256 amountBMin, address to, uint256 deadline) external returns (uint256 amountA, uint256 amountB)
+    ]"#;
 );
 
 pub const U128_0X10000000000000000: u128 = 18446744073709551616;
@@ -56,6 +75,107 @@ pub struct UniswapV2Pool {
     pub reserve_0: u128,
     pub reserve_1: u128,
     pub fee: u32,
+    #[serde(default)]
+    pub creation_block: u64,
+    // Value of `kLast` on the pair, used to compute the protocol fee minted on liquidity changes
+    #[serde(default)]
+    pub k_last: U256,
+    // Rolling window of the latest Sync updates, only recorded once enabled with `track_reserves`
+    #[serde(skip)]
+    pub reserve_tracker: Option<ReserveTracker>,
+    // Portion of the swap fee sent to the protocol instead of the reserves, in basis points of the amount in
+    #[serde(default)]
+    pub protocol_fee_bps: u32,
+    // Overrides the swap math derived from `fee`, inherited from the factory. Custom models cannot be serialized
+    #[serde(default)]
+    pub fee_model: Option<FeeModel>,
+    // Address of the factory that created the pool, zero if the pool was not discovered through a factory
+    #[serde(default)]
+    pub factory: H160,
+}
+
+/// The fee formula used to compute the amount out of a swap, for forks deviating from the Uniswap V2 math.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum FeeModel {
+    /// The Uniswap V2 formula with a fee of `bps` basis points taken from the amount in.
+    UniswapV2 { bps: u32 },
+    /// A custom formula taking `amount_in`, `reserve_in` and `reserve_out` and returning the amount out.
+    ///
+    /// Functions cannot be serialized, so serializing a pool or factory with a custom model returns an error.
+    #[serde(skip)]
+    Custom(fn(U256, U256, U256) -> U256),
+}
+
+impl FeeModel {
+    /// Returns the amount received for `amount_in` given `reserve_in` and `reserve_out`.
+    pub fn amount_out(&self, amount_in: U256, reserve_in: U256, reserve_out: U256) -> U256 {
+        match self {
+            FeeModel::UniswapV2 { bps } => {
+                if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+                    return U256::zero();
+                }
+
+                let amount_in_with_fee = amount_in * U256::from(10000_u32.saturating_sub(*bps));
+                let numerator = amount_in_with_fee * reserve_out;
+                let denominator = reserve_in * U256::from(10000) + amount_in_with_fee;
+
+                numerator / denominator
+            }
+            FeeModel::Custom(amount_out) => amount_out(amount_in, reserve_in, reserve_out),
+        }
+    }
+}
+
+/// Records the reserves of the last `capacity` Sync updates applied to a pool, along with their block numbers.
+#[derive(Debug, Clone, Default)]
+pub struct ReserveTracker {
+    pub capacity: usize,
+    pub updates: VecDeque<(u64, u128, u128)>,
+}
+
+impl ReserveTracker {
+    pub fn new(capacity: usize) -> Self {
+        ReserveTracker {
+            capacity,
+            updates: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Records the reserves at `block_number`, evicting the oldest update once the tracker is full.
+    pub fn record(&mut self, block_number: u64, reserve_0: u128, reserve_1: u128) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.updates.len() == self.capacity {
+            self.updates.pop_front();
+        }
+
+        self.updates.push_back((block_number, reserve_0, reserve_1));
+    }
+
+    /// Returns the average absolute change of `reserve_0` plus `reserve_1` per block over the recorded updates.
+    ///
+    /// Returns `None` if fewer than two updates were recorded or they all happened in the same block.
+    pub fn velocity(&self) -> Option<f64> {
+        let (first_block, ..) = *self.updates.front()?;
+        let (last_block, ..) = *self.updates.back()?;
+
+        if last_block <= first_block {
+            return None;
+        }
+
+        let total_change = self
+            .updates
+            .iter()
+            .zip(self.updates.iter().skip(1))
+            .map(|((_, prev_0, prev_1), (_, next_0, next_1))| {
+                prev_0.abs_diff(*next_0) as f64 + prev_1.abs_diff(*next_1) as f64
+            })
+            .sum::<f64>();
+
+        Some(total_change / (last_block - first_block) as f64)
+    }
 }
 
 #[async_trait]
@@ -78,11 +198,30 @@ impl AutomatedMarketMaker for UniswapV2Pool {
     #[instrument(skip(self, middleware), level = "debug")]
     async fn populate_data<M: Middleware>(
         &mut self,
-        _block_number: Option<u64>,
+        block_number: Option<u64>,
         middleware: Arc<M>,
     ) -> Result<(), AMMError<M>> {
         batch_request::get_v2_pool_data_batch_request(self, middleware.clone()).await?;
 
+        let mut k_last_call = IUniswapV2Pair::new(self.address, middleware.clone()).k_last();
+        if let Some(block_number) = block_number {
+            k_last_call = k_last_call.block(block_number);
+        }
+
+        //Pairs without `kLast` revert and do not accrue a protocol fee, any other error is propagated
+        self.k_last = match k_last_call.call().await {
+            Ok(k_last) => k_last,
+            Err(err) if err.is_revert() => U256::zero(),
+            Err(err) => return Err(err.into()),
+        };
+
+        //Forks with per pair fees report them via `fee()`, standard pairs keep the factory fee
+        if let Some(fee) =
+            UniswapV2Pool::get_pair_fee(self.address, block_number, middleware.clone()).await?
+        {
+            self.fee = fee;
+        }
+
         Ok(())
     }
 
@@ -95,12 +234,25 @@ impl AutomatedMarketMaker for UniswapV2Pool {
         let event_signature = log.topics[0];
 
         if event_signature == SYNC_EVENT_SIGNATURE {
+            let block_number = log.block_number;
             let sync_event = SyncFilter::decode_log(&RawLog::from(log))?;
+
+            //Reserves are stored as uint112 on chain, larger values indicate a non standard pair
+            if sync_event.reserve_0 > U112_MAX || sync_event.reserve_1 > U112_MAX {
+                return Err(EventLogError::ReservesOverflow(self.address));
+            }
+
             tracing::info!(reserve_0 = sync_event.reserve_0, reserve_1 = sync_event.reserve_1, address = ?self.address, "UniswapV2 sync event");
 
             self.reserve_0 = sync_event.reserve_0;
             self.reserve_1 = sync_event.reserve_1;
 
+            if let (Some(tracker), Some(block_number)) =
+                (self.reserve_tracker.as_mut(), block_number)
+            {
+                tracker.record(block_number.as_u64(), self.reserve_0, self.reserve_1);
+            }
+
             Ok(())
         } else {
             Err(EventLogError::InvalidEventSignature)
@@ -115,19 +267,28 @@ impl AutomatedMarketMaker for UniswapV2Pool {
         vec![self.token_a, self.token_b]
     }
 
+    fn reserves(&self) -> Vec<u128> {
+        vec![self.reserve_0, self.reserve_1]
+    }
+
+    fn get_reserve(&self, token: H160) -> u128 {
+        if token == self.token_a {
+            self.reserve_0
+        } else if token == self.token_b {
+            self.reserve_1
+        } else {
+            0
+        }
+    }
+
     fn simulate_swap(&self, token_in: H160, amount_in: U256) -> Result<U256, SwapSimulationError> {
+        self.check_swap_liquidity(amount_in)?;
+        self.check_swap_overflow(token_in, amount_in)?;
+
         if self.token_a == token_in {
-            Ok(self.get_amount_out(
-                amount_in,
-                U256::from(self.reserve_0),
-                U256::from(self.reserve_1),
-            ))
+            self.swap_amount_out(amount_in, self.reserve_0_u256(), self.reserve_1_u256())
         } else {
-            Ok(self.get_amount_out(
-                amount_in,
-                U256::from(self.reserve_1),
-                U256::from(self.reserve_0),
-            ))
+            self.swap_amount_out(amount_in, self.reserve_1_u256(), self.reserve_0_u256())
         }
     }
 
@@ -136,34 +297,31 @@ impl AutomatedMarketMaker for UniswapV2Pool {
         token_in: H160,
         amount_in: U256,
     ) -> Result<U256, SwapSimulationError> {
+        self.check_swap_liquidity(amount_in)?;
+        self.check_swap_overflow(token_in, amount_in)?;
+
         if self.token_a == token_in {
-            let amount_out = self.get_amount_out(
-                amount_in,
-                U256::from(self.reserve_0),
-                U256::from(self.reserve_1),
-            );
+            let amount_out =
+                self.swap_amount_out(amount_in, self.reserve_0_u256(), self.reserve_1_u256())?;
 
             tracing::trace!(?amount_out);
             tracing::trace!(?self.reserve_0, ?self.reserve_1, "pool reserves before");
 
-            self.reserve_0 += amount_in.as_u128();
+            self.reserve_0 += (amount_in - self.protocol_fee_amount(amount_in)).as_u128();
             self.reserve_1 -= amount_out.as_u128();
 
             tracing::trace!(?self.reserve_0, ?self.reserve_1, "pool reserves after");
 
             Ok(amount_out)
         } else {
-            let amount_out = self.get_amount_out(
-                amount_in,
-                U256::from(self.reserve_1),
-                U256::from(self.reserve_0),
-            );
+            let amount_out =
+                self.swap_amount_out(amount_in, self.reserve_1_u256(), self.reserve_0_u256())?;
 
             tracing::trace!(?amount_out);
             tracing::trace!(?self.reserve_0, ?self.reserve_1, "pool reserves before");
 
             self.reserve_0 -= amount_out.as_u128();
-            self.reserve_1 += amount_in.as_u128();
+            self.reserve_1 += (amount_in - self.protocol_fee_amount(amount_in)).as_u128();
 
             tracing::trace!(?self.reserve_0, ?self.reserve_1, "pool reserves after");
 
@@ -178,6 +336,91 @@ impl AutomatedMarketMaker for UniswapV2Pool {
             self.token_a
         }
     }
+
+    fn token_pair(&self) -> Option<TokenPair> {
+        Some(TokenPair::new(self.token_a, self.token_b))
+    }
+
+    fn state_snapshot(&self) -> AmmState {
+        AmmState::UniswapV2Pool {
+            reserve_0: self.reserve_0,
+            reserve_1: self.reserve_1,
+        }
+    }
+
+    fn restore_state(&mut self, state: AmmState) {
+        if let AmmState::UniswapV2Pool {
+            reserve_0,
+            reserve_1,
+        } = state
+        {
+            self.reserve_0 = reserve_0;
+            self.reserve_1 = reserve_1;
+        }
+    }
+
+    //Fee of 300 => 30 bps, custom fee models do not expose their fee and fall back to `fee`
+    fn fee_bps(&self) -> u32 {
+        match self.fee_model {
+            Some(FeeModel::UniswapV2 { bps }) => bps,
+            _ => self.fee / 10,
+        }
+    }
+
+    //Both reserves hold the same value at the pool price, so the TVL is twice the base reserve
+    fn total_value_locked(&self, base_token: H160) -> Result<f64, ArithmeticError> {
+        let (reserve, decimals) = if base_token == self.token_a {
+            (self.reserve_0, self.token_a_decimals)
+        } else if base_token == self.token_b {
+            (self.reserve_1, self.token_b_decimals)
+        } else {
+            return Err(ArithmeticError::InvalidToken(base_token));
+        };
+
+        Ok(2.0 * reserve as f64 / 10_f64.powi(decimals as i32))
+    }
+
+    fn memory_size_bytes(&self) -> usize {
+        let reserve_tracker_size = self.reserve_tracker.as_ref().map_or(0, |tracker| {
+            tracker.updates.capacity() * std::mem::size_of::<(u64, u128, u128)>()
+        });
+
+        std::mem::size_of::<Self>() + reserve_tracker_size
+    }
+
+    fn sanity_check(&self) -> Vec<SanityIssue> {
+        let mut issues = vec![];
+
+        //Reserves are stored as uint112 on chain
+        if self.reserve_0 == 0
+            || self.reserve_1 == 0
+            || self.reserve_0 > U112_MAX
+            || self.reserve_1 > U112_MAX
+        {
+            issues.push(SanityIssue::ReservesOutOfRange);
+        }
+
+        for decimals in [self.token_a_decimals, self.token_b_decimals] {
+            if decimals > MAX_TOKEN_DECIMALS {
+                issues.push(SanityIssue::DecimalsUnusual(decimals));
+            }
+        }
+
+        //Fee of 300 => 0.3%, anything above 10% is treated as invalid
+        if self.fee > 10000 {
+            issues.push(SanityIssue::FeeOutOfRange(self.fee));
+        }
+
+        if self.token_a == self.token_b {
+            issues.push(SanityIssue::TokensSameAddress);
+        }
+
+        if self.address == self.token_a || self.address == self.token_b {
+            issues.push(SanityIssue::AddressMismatch);
+        }
+
+        issues
+    }
 }
 
 impl UniswapV2Pool {
@@ -191,6 +434,7 @@ impl UniswapV2Pool {
         reserve_0: u128,
         reserve_1: u128,
         fee: u32,
+        creation_block: u64,
     ) -> UniswapV2Pool {
         UniswapV2Pool {
             address,
@@ -201,6 +445,12 @@ impl UniswapV2Pool {
             reserve_0,
             reserve_1,
             fee,
+            creation_block,
+            k_last: U256::zero(),
+            reserve_tracker: None,
+            protocol_fee_bps: 0,
+            fee_model: None,
+            factory: H160::zero(),
         }
     }
 
@@ -219,6 +469,12 @@ impl UniswapV2Pool {
             reserve_0: 0,
             reserve_1: 0,
             fee,
+            creation_block: 0,
+            k_last: U256::zero(),
+            reserve_tracker: None,
+            protocol_fee_bps: 0,
+            fee_model: None,
+            factory: H160::zero(),
         };
 
         pool.populate_data(None, middleware.clone()).await?;
@@ -230,6 +486,35 @@ impl UniswapV2Pool {
         Ok(pool)
     }
 
+    /// Returns the fee reported by `fee()` on the pair, for forks that configure fees per pair.
+    ///
+    /// The fee is expected in the unit of `UniswapV2Pool::fee`, ie. 300 for 0.3%. Returns `None` if the pair does not
+    /// implement `fee()`, and `AMMError::InvalidPairFee` if the reported fee is 100% or more.
+    pub async fn get_pair_fee<M: Middleware>(
+        pair_address: H160,
+        block_number: Option<u64>,
+        middleware: Arc<M>,
+    ) -> Result<Option<u32>, AMMError<M>> {
+        let mut fee_call = IUniswapV2Pair::new(pair_address, middleware).fee();
+        if let Some(block_number) = block_number {
+            fee_call = fee_call.block(block_number);
+        }
+
+        let fee = match fee_call.call().await {
+            Ok(fee) => fee,
+            //Standard V2 pairs do not implement `fee()` and revert
+            Err(err) if err.is_revert() => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        //Fee of 100000 => 100%
+        if fee >= 100_000 {
+            return Err(AMMError::InvalidPairFee(pair_address, fee));
+        }
+
+        Ok(Some(fee))
+    }
+
     /// Creates a new instance of a the pool from a `PairCreated` event log.
     ///
     /// This method syncs the pool data.
@@ -241,8 +526,14 @@ impl UniswapV2Pool {
         let event_signature = log.topics[0];
 
         if event_signature == PAIR_CREATED_EVENT_SIGNATURE {
+            let creation_block = log.block_number.unwrap_or_default().as_u64();
             let pair_created_event = factory::PairCreatedFilter::decode_log(&RawLog::from(log))?;
-            UniswapV2Pool::new_from_address(pair_created_event.pair, fee, middleware).await
+
+            let mut pool =
+                UniswapV2Pool::new_from_address(pair_created_event.pair, fee, middleware).await?;
+            pool.creation_block = creation_block;
+
+            Ok(pool)
         } else {
             Err(EventLogError::InvalidEventSignature)?
         }
@@ -255,6 +546,7 @@ impl UniswapV2Pool {
         let event_signature = log.topics[0];
 
         if event_signature == PAIR_CREATED_EVENT_SIGNATURE {
+            let creation_block = log.block_number.unwrap_or_default().as_u64();
             let pair_created_event = factory::PairCreatedFilter::decode_log(&RawLog::from(log))?;
 
             Ok(UniswapV2Pool {
@@ -266,17 +558,177 @@ impl UniswapV2Pool {
                 reserve_0: 0,
                 reserve_1: 0,
                 fee: 0,
+                creation_block,
+                k_last: U256::zero(),
+                reserve_tracker: None,
+                protocol_fee_bps: 0,
+                fee_model: None,
+                factory: H160::zero(),
             })
         } else {
             Err(EventLogError::InvalidEventSignature)?
         }
     }
 
-    /// Returns the swap fee of the pool.
+    /// Creates a new empty pool from a `PairCreated` log, with the swap fee set to `fee`.
+    pub fn from_log_and_fee(log: Log, fee: u32) -> Result<Self, EventLogError> {
+        let mut pool = UniswapV2Pool::new_empty_pool_from_log(log)?;
+        pool.fee = fee;
+
+        Ok(pool)
+    }
+
+    /// Returns the difference between the decimals of `token_a` and `token_b`, used to normalize the reserves when pricing.
+    pub fn decimal_shift(&self) -> i8 {
+        self.token_a_decimals as i8 - self.token_b_decimals as i8
+    }
+
+    /// Returns the EIP-55 checksummed address of the pool.
+    pub fn checksum_address(&self) -> String {
+        to_checksum(&self.address, None)
+    }
+
+    /// Returns the pool as JSON in the shape of the `@uniswap/sdk` `Pair` constructor arguments, with the reserves as token amounts.
+    ///
+    /// Token symbols are not tracked by the pool and are emitted as `null`.
+    pub fn to_sdk_json(&self, chain_id: u64) -> serde_json::Value {
+        let token_amount = |token: &H160, decimals: u8, reserve: u128| {
+            json!({
+                "token": {
+                    "chainId": chain_id,
+                    "address": to_checksum(token, None),
+                    "decimals": decimals,
+                    "symbol": null,
+                },
+                "amount": reserve.to_string(),
+            })
+        };
+
+        json!({
+            "address": self.checksum_address(),
+            "tokenAmountA": token_amount(&self.token_a, self.token_a_decimals, self.reserve_0),
+            "tokenAmountB": token_amount(&self.token_b, self.token_b_decimals, self.reserve_1),
+        })
+    }
+
+    /// Returns the swap fee of the pool, where 300 is a 0.3% fee.
+    #[deprecated(note = "the unit is ambiguous, use `fee_bps` instead")]
     pub fn fee(&self) -> u32 {
         self.fee
     }
 
+    /// Returns the amount of LP tokens permanently locked in the pool.
+    pub fn locked_lp_tokens() -> u128 {
+        MINIMUM_LIQUIDITY
+    }
+
+    /// Returns the amount of LP tokens that can be redeemed for the reserves, given the LP token `total_supply`.
+    pub fn claimable_lp_tokens(total_supply: U256) -> U256 {
+        total_supply.saturating_sub(U256::from(Self::locked_lp_tokens()))
+    }
+
+    /// Returns the amount of LP tokens minted for depositing `amount_0` of `token_a` and `amount_1` of `token_b`, given the LP token `total_supply`.
+    ///
+    /// For the initial deposit, this is `sqrt(amount_0 * amount_1) - MINIMUM_LIQUIDITY`, otherwise the smaller share of the reserves
+    /// deposited times the total supply. Returns zero where the pair would revert.
+    pub fn calculate_liquidity_provision(
+        &self,
+        amount_0: U256,
+        amount_1: U256,
+        total_supply: U256,
+    ) -> U256 {
+        if total_supply.is_zero() {
+            //The square root of a product of two U256 always fits in a U256
+            let liquidity =
+                U256::try_from(amount_0.full_mul(amount_1).integer_sqrt()).unwrap_or(U256::MAX);
+
+            return liquidity.saturating_sub(U256::from(MINIMUM_LIQUIDITY));
+        }
+
+        if self.reserve_0 == 0 || self.reserve_1 == 0 {
+            return U256::zero();
+        }
+
+        let share = |amount: U256, reserve: u128| {
+            U256::try_from(amount.full_mul(total_supply) / U256::from(reserve)).unwrap_or(U256::MAX)
+        };
+
+        share(amount_0, self.reserve_0).min(share(amount_1, self.reserve_1))
+    }
+
+    /// Returns the storage overrides setting the pool reserves to the local state, for use in `eth_call` state overrides.
+    ///
+    /// Assumes the canonical UniswapV2Pair storage layout, where `reserve0`, `reserve1` and `blockTimestampLast`
+    /// are packed in a single slot. `blockTimestampLast` is set to zero.
+    pub fn storage_overrides(&self) -> HashMap<H256, H256> {
+        let packed_reserves = self.reserve_0_u256() | (self.reserve_1_u256() << 112);
+
+        let mut slot_value = [0u8; 32];
+        packed_reserves.to_big_endian(&mut slot_value);
+
+        HashMap::from([(
+            H256::from_low_u64_be(RESERVES_STORAGE_SLOT),
+            H256::from(slot_value),
+        )])
+    }
+
+    /// Returns `reserve_0` as a U256.
+    pub fn reserve_0_u256(&self) -> U256 {
+        U256::from(self.reserve_0)
+    }
+
+    /// Returns `reserve_1` as a U256.
+    pub fn reserve_1_u256(&self) -> U256 {
+        U256::from(self.reserve_1)
+    }
+
+    /// Returns `sqrt(reserve_0 * reserve_1)`, a price independent measure of the pool liquidity.
+    ///
+    /// Useful to rank pools by depth without knowing the token prices.
+    pub fn liquidity_score(&self) -> f64 {
+        //The product of two u112 reserves fits in a U256
+        let k = self.reserve_0_u256() * self.reserve_1_u256();
+
+        BigFloat::from(k.integer_sqrt().as_u128()).to_f64()
+    }
+
+    /// Returns the virtual liquidity `L = sqrt(reserve_0 * reserve_1)` of the pool, with the reserves adjusted by the token decimals.
+    ///
+    /// Comparable to the liquidity of a Uniswap V3 pool at the current price. Computed with `BigFloat` to avoid overflowing the reserve product.
+    pub fn liquidity_at_price(&self) -> f64 {
+        let reserve_0 = BigFloat::from(self.reserve_0)
+            .div(&BigFloat::from(10_u128).pow(&BigFloat::from(self.token_a_decimals)));
+        let reserve_1 = BigFloat::from(self.reserve_1)
+            .div(&BigFloat::from(10_u128).pow(&BigFloat::from(self.token_b_decimals)));
+
+        reserve_0.mul(&reserve_1).sqrt().to_f64()
+    }
+
+    /// Returns a confidence in the pool price between 0 and 1, based on how far the smaller reserve exceeds `min_reserve`.
+    ///
+    /// Pools with a smaller reserve at or below `min_reserve` score 0, approaching 1 as the reserve grows.
+    pub fn price_confidence(&self, min_reserve: u128) -> f64 {
+        let smaller_reserve = self.reserve_0.min(self.reserve_1);
+
+        if smaller_reserve <= min_reserve {
+            return 0.0;
+        }
+
+        1.0 - min_reserve as f64 / smaller_reserve as f64
+    }
+
+    /// Starts recording the last `capacity` Sync updates applied through `sync_from_log`, replacing any existing tracker.
+    pub fn track_reserves(&mut self, capacity: usize) {
+        self.reserve_tracker = Some(ReserveTracker::new(capacity));
+    }
+
+    /// Returns the average absolute reserve change per block over the tracked Sync updates.
+    ///
+    /// Returns `None` if reserve tracking is disabled or not enough updates were recorded. See `track_reserves`.
+    pub fn reserve_velocity(&self) -> Option<f64> {
+        self.reserve_tracker.as_ref()?.velocity()
+    }
+
     /// Returns whether the pool data is populated.
     pub fn data_is_populated(&self) -> bool {
         !(self.token_a.is_zero()
@@ -305,6 +757,47 @@ impl UniswapV2Pool {
         Ok((reserve_0, reserve_1))
     }
 
+    /// Returns the reserves of the pool at `block_number`.
+    pub async fn get_reserves_at_block<M: Middleware>(
+        &self,
+        block_number: u64,
+        middleware: Arc<M>,
+    ) -> Result<(u128, u128), AMMError<M>> {
+        let v2_pair = IUniswapV2Pair::new(self.address, middleware);
+        let (reserve_0, reserve_1, _) = v2_pair.get_reserves().block(block_number).call().await?;
+
+        Ok((reserve_0, reserve_1))
+    }
+
+    /// Simulates the swap against the reserves read at each of `blocks` and returns the smallest output.
+    ///
+    /// Guards against acting on reserves manipulated within a single block, ie. by a flash loan.
+    /// If `blocks` is empty, the swap is simulated against the local reserves.
+    pub async fn conservative_amount_out<M: Middleware>(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        blocks: &[u64],
+        middleware: Arc<M>,
+    ) -> Result<U256, AMMError<M>> {
+        if blocks.is_empty() {
+            return Ok(self.simulate_swap(token_in, amount_in)?);
+        }
+
+        let mut pool = self.clone();
+        let mut min_amount_out = U256::MAX;
+
+        for block_number in blocks {
+            (pool.reserve_0, pool.reserve_1) = self
+                .get_reserves_at_block(*block_number, middleware.clone())
+                .await?;
+
+            min_amount_out = min_amount_out.min(pool.simulate_swap(token_in, amount_in)?);
+        }
+
+        Ok(min_amount_out)
+    }
+
     pub async fn get_token_decimals<M: Middleware>(
         &mut self,
         middleware: Arc<M>,
@@ -358,18 +851,21 @@ impl UniswapV2Pool {
     ///
     /// Returned as a Q64 fixed point number.
     pub fn calculate_price_64_x_64(&self, base_token: H160) -> Result<u128, ArithmeticError> {
-        let decimal_shift = self.token_a_decimals as i8 - self.token_b_decimals as i8;
+        if base_token != self.token_a && base_token != self.token_b {
+            return Err(ArithmeticError::InvalidToken(base_token));
+        }
+
+        let decimal_shift = self.decimal_shift();
 
         let (r_0, r_1) = if decimal_shift < 0 {
             (
-                U256::from(self.reserve_0)
-                    * U256::from(10u128.pow(decimal_shift.unsigned_abs() as u32)),
-                U256::from(self.reserve_1),
+                self.reserve_0_u256() * U256::from(10u128.pow(decimal_shift.unsigned_abs() as u32)),
+                self.reserve_1_u256(),
             )
         } else {
             (
-                U256::from(self.reserve_0),
-                U256::from(self.reserve_1) * U256::from(10u128.pow(decimal_shift as u32)),
+                self.reserve_0_u256(),
+                self.reserve_1_u256() * U256::from(10u128.pow(decimal_shift as u32)),
             )
         };
 
@@ -386,35 +882,195 @@ impl UniswapV2Pool {
         }
     }
 
+    /// Calculates the marginal price of the base token in terms of the quote token, net of the swap fee.
+    ///
+    /// This is the best rate achievable for an infinitesimally small trade.
+    pub fn calculate_price_with_fee(&self, base_token: H160) -> Result<f64, ArithmeticError> {
+        //Fee of 300 => 0.3%
+        Ok(self.calculate_price(base_token)? * (1.0 - self.fee_fraction()))
+    }
+
+    /// Samples the ask side of the constant product curve as order book like levels for `base_token`.
+    ///
+    /// Level `i` is priced `i * step_bps` above the current price of `base_token`, and holds the cumulative amount of
+    /// `base_token` that can be bought before the pool price reaches it. Fees are not applied, they only change the amount
+    /// paid, not the amount of `base_token` available at each price. Returns an empty chart if `base_token` is not in the
+    /// pool, the pool is empty or its reserves exceed uint112.
+    pub fn depth_chart(&self, base_token: H160, levels: usize, step_bps: u32) -> Vec<(f64, U256)> {
+        let reserve_base = if base_token == self.token_a {
+            self.reserve_0_u256()
+        } else if base_token == self.token_b {
+            self.reserve_1_u256()
+        } else {
+            return vec![];
+        };
+
+        let price = match self.calculate_price(base_token) {
+            Ok(price) if self.reserve_0 != 0 && self.reserve_1 != 0 => price,
+            _ => return vec![],
+        };
+
+        //The pair caps its reserves at uint112, larger reserves would overflow the squared reserve below
+        if self.reserve_0 > U112_MAX || self.reserve_1 > U112_MAX {
+            return vec![];
+        }
+
+        let reserve_base_squared = reserve_base * reserve_base * U256::from(10000);
+
+        (1..=levels as u64)
+            .map(|level| {
+                let price_bps = (level.saturating_mul(step_bps as u64)).saturating_add(10000);
+
+                //At a price `p * price_bps / 10000` the base reserve is `reserve_base * sqrt(10000 / price_bps)`
+                let remaining_base = (reserve_base_squared / U256::from(price_bps)).integer_sqrt();
+
+                (
+                    price * price_bps as f64 / 10000.0,
+                    reserve_base - remaining_base,
+                )
+            })
+            .collect()
+    }
+
+    /// Returns `SwapSimulationError::InsufficientLiquidity` if the pool is empty or `amount_in` is zero.
+    fn check_swap_liquidity(&self, amount_in: U256) -> Result<(), SwapSimulationError> {
+        if amount_in.is_zero() || self.reserve_0 == 0 || self.reserve_1 == 0 {
+            Err(SwapSimulationError::InsufficientLiquidity)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns `SwapSimulationError::ReservesOverflow` if swapping `amount_in` would push the input reserve past uint112.
+    ///
+    /// The pair reverts on such swaps, and larger amounts could overflow the U256 math in `get_amount_out`.
+    fn check_swap_overflow(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<(), SwapSimulationError> {
+        let reserve_in = if self.token_a == token_in {
+            self.reserve_0
+        } else {
+            self.reserve_1
+        };
+
+        if U256::from(reserve_in).saturating_add(amount_in) > U256::from(U112_MAX) {
+            Err(SwapSimulationError::ReservesOverflow)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the amount out of a swap, or `SwapSimulationError::InsufficientLiquidity` if it would drain `reserve_out`.
+    ///
+    /// The Uniswap V2 math never reaches `reserve_out`, but a custom fee model can. Returns
+    /// `SwapSimulationError::InvalidFee` if the fee exceeds 100%.
+    fn swap_amount_out(
+        &self,
+        amount_in: U256,
+        reserve_in: U256,
+        reserve_out: U256,
+    ) -> Result<U256, SwapSimulationError> {
+        let amount_out = self.checked_amount_out(amount_in, reserve_in, reserve_out)?;
+
+        if amount_out >= reserve_out {
+            Err(SwapSimulationError::InsufficientLiquidity)
+        } else {
+            Ok(amount_out)
+        }
+    }
+
+    /// Returns the portion of the swap fee on `amount_in` that is sent to the protocol rather than added to the reserves.
+    ///
+    /// The protocol fee is part of the swap fee, `protocol_fee_bps` is capped at `fee`. It does not change the amount out of
+    /// a swap, only how much of `amount_in` the reserves receive.
+    pub fn protocol_fee_amount(&self, amount_in: U256) -> U256 {
+        let protocol_fee_bps = self.protocol_fee_bps.min(self.fee_bps());
+
+        amount_in * U256::from(protocol_fee_bps) / U256::from(10000)
+    }
+
     /// Calculates the amount received for a given `amount_in` `reserve_in` and `reserve_out`.
+    ///
+    /// Returns zero if the fee exceeds 100%, `simulate_swap` returns `SwapSimulationError::InvalidFee` instead.
     pub fn get_amount_out(&self, amount_in: U256, reserve_in: U256, reserve_out: U256) -> U256 {
+        self.checked_amount_out(amount_in, reserve_in, reserve_out)
+            .unwrap_or_default()
+    }
+
+    /// Calculates the amount received for a given `amount_in` `reserve_in` and `reserve_out`.
+    ///
+    /// Returns `SwapSimulationError::InvalidFee` if the fee exceeds 100%.
+    fn checked_amount_out(
+        &self,
+        amount_in: U256,
+        reserve_in: U256,
+        reserve_out: U256,
+    ) -> Result<U256, SwapSimulationError> {
         tracing::trace!(?amount_in, ?reserve_in, ?reserve_out);
 
+        if let Some(fee_model) = self.fee_model {
+            return Ok(fee_model.amount_out(amount_in, reserve_in, reserve_out));
+        }
+
         if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
-            return U256::zero();
+            return Ok(U256::zero());
         }
-        let fee = (10000 - (self.fee / 10)) / 10; //Fee of 300 => (10,000 - 30) / 10  = 997
+        //Fee of 300 => 10,000 - 30 = 9970, over a base of 10,000
+        let fee = 10000_u32
+            .checked_sub(self.fee_bps())
+            .ok_or(SwapSimulationError::InvalidFee(self.fee_bps()))?;
         let amount_in_with_fee = amount_in * U256::from(fee);
         let numerator = amount_in_with_fee * reserve_out;
-        let denominator = reserve_in * U256::from(1000) + amount_in_with_fee;
+        let denominator = reserve_in * U256::from(10000) + amount_in_with_fee;
 
         tracing::trace!(?fee, ?amount_in_with_fee, ?numerator, ?denominator);
 
-        numerator / denominator
+        Ok(numerator / denominator)
     }
 
-    /// Returns the calldata for a swap.
-    pub fn swap_calldata(
+    /// Verifies that the router quotes the same amount out as the local swap math for `amount_in` of `token_a`.
+    ///
+    /// Useful to check that the pool fee is configured correctly when onboarding a new fork.
+    pub async fn assert_parity_with_router<M: Middleware>(
         &self,
-        amount_0_out: U256,
-        amount_1_out: U256,
-        to: H160,
-        calldata: Vec<u8>,
-    ) -> Result<Bytes, ethers::abi::Error> {
-        let input_tokens = vec![
-            Token::Uint(amount_0_out),
-            Token::Uint(amount_1_out),
-            Token::Address(to),
+        router: H160,
+        amount_in: U256,
+        middleware: Arc<M>,
+    ) -> Result<(), AMMError<M>> {
+        let router_contract = IUniswapV2Router::new(router, middleware);
+        let amounts = router_contract
+            .get_amounts_out(amount_in, vec![self.token_a, self.token_b])
+            .call()
+            .await?;
+
+        let router_amount_out = amounts.last().copied().unwrap_or_default();
+        let local_amount_out = self.simulate_swap(self.token_a, amount_in)?;
+
+        if router_amount_out != local_amount_out {
+            return Err(AMMError::RouterParityMismatch(
+                router,
+                router_amount_out,
+                local_amount_out,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the calldata for a swap.
+    pub fn swap_calldata(
+        &self,
+        amount_0_out: U256,
+        amount_1_out: U256,
+        to: H160,
+        calldata: Vec<u8>,
+    ) -> Result<Bytes, ethers::abi::Error> {
+        let input_tokens = vec![
+            Token::Uint(amount_0_out),
+            Token::Uint(amount_1_out),
+            Token::Address(to),
             Token::Bytes(calldata),
         ];
 
@@ -422,6 +1078,58 @@ impl UniswapV2Pool {
             .function("swap")?
             .encode_input(&input_tokens)
     }
+
+    /// Returns the V2 Router `addLiquidity` calldata to deposit into the pool.
+    ///
+    /// Amounts are ordered as `token_a`, `token_b`. The deadline is set to `U256::MAX`, enforce it off chain if needed.
+    pub fn mint_calldata(
+        &self,
+        amount_0_desired: U256,
+        amount_1_desired: U256,
+        amount_0_min: U256,
+        amount_1_min: U256,
+        to: H160,
+    ) -> Result<Bytes, ethers::abi::Error> {
+        let input_tokens = vec![
+            Token::Address(self.token_a),
+            Token::Address(self.token_b),
+            Token::Uint(amount_0_desired),
+            Token::Uint(amount_1_desired),
+            Token::Uint(amount_0_min),
+            Token::Uint(amount_1_min),
+            Token::Address(to),
+            Token::Uint(U256::MAX),
+        ];
+
+        IUNISWAPV2ROUTER_ABI
+            .function("addLiquidity")?
+            .encode_input(&input_tokens)
+    }
+
+    /// Returns the V2 Router `removeLiquidity` calldata to burn `liquidity` LP tokens of the pool.
+    ///
+    /// Amounts are ordered as `token_a`, `token_b`. The deadline is set to `U256::MAX`, enforce it off chain if needed.
+    pub fn burn_calldata(
+        &self,
+        liquidity: U256,
+        amount_0_min: U256,
+        amount_1_min: U256,
+        to: H160,
+    ) -> Result<Bytes, ethers::abi::Error> {
+        let input_tokens = vec![
+            Token::Address(self.token_a),
+            Token::Address(self.token_b),
+            Token::Uint(liquidity),
+            Token::Uint(amount_0_min),
+            Token::Uint(amount_1_min),
+            Token::Address(to),
+            Token::Uint(U256::MAX),
+        ];
+
+        IUNISWAPV2ROUTER_ABI
+            .function("removeLiquidity")?
+            .encode_input(&input_tokens)
+    }
 }
 
 pub const U256_0XFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF: Uint<256, 4> =
@@ -540,18 +1248,109 @@ pub fn q64_to_f64(x: u128) -> f64 {
         .to_f64()
 }
 
+#[cfg(test)]
+impl UniswapV2Pool {
+    /// Returns a pool between two 18 decimal tokens with a 0.3% fee, shared by the test modules.
+    pub(crate) fn test_pool(
+        address: u64,
+        token_a: H160,
+        token_b: H160,
+        reserve_0: u128,
+        reserve_1: u128,
+    ) -> UniswapV2Pool {
+        UniswapV2Pool {
+            address: H160::from_low_u64_be(address),
+            token_a,
+            token_a_decimals: 18,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0,
+            reserve_1,
+            fee: 300,
+            ..Default::default()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{str::FromStr, sync::Arc};
 
     use ethers::{
-        providers::{Http, Provider},
-        types::{H160, U256},
+        abi::Token,
+        providers::{Http, JsonRpcError, MockResponse, Provider},
+        types::{Bytes, Log, H160, H256, U256, U64},
     };
 
-    use crate::amm::AutomatedMarketMaker;
+    use crate::{
+        amm::{factory::AutomatedMarketMakerFactory, AutomatedMarketMaker, SanityIssue, AMM},
+        errors::{AMMError, ArithmeticError, EventLogError, SwapSimulationError},
+    };
 
-    use super::UniswapV2Pool;
+    use super::{
+        batch_request,
+        constant::{RESERVES_STORAGE_SLOT, U128_MAX, U256_U128_MAX},
+        factory::{
+            UniswapV2Factory, PAIR_CREATED_EVENT_SIGNATURE, UNISWAP_V2_CREATION_BLOCK_MAINNET,
+            UNISWAP_V2_FACTORY_MAINNET,
+        },
+        FeeModel, UniswapV2Pool, SYNC_EVENT_SIGNATURE, U112_MAX,
+    };
+
+    #[test]
+    fn test_uniswap_v2_factory_deployments() -> eyre::Result<()> {
+        let deployments = [
+            (
+                UniswapV2Factory::uniswap_v2_mainnet(),
+                "0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f",
+                1,
+            ),
+            (
+                UniswapV2Factory::uniswap_v2_arbitrum(),
+                "0xf1D7CC64Fb4452F05c498126312eBE29f30Fbcf9",
+                42161,
+            ),
+            (
+                UniswapV2Factory::uniswap_v2_optimism(),
+                "0x0c3c1c532F1e39EdF36BE9Fe0bE1410313E074Bf",
+                10,
+            ),
+            (
+                UniswapV2Factory::uniswap_v2_polygon(),
+                "0x9e5A52f57b3038F1B8EeE45F28b3C1967e22799C",
+                137,
+            ),
+        ];
+
+        for (factory, address, chain_id) in deployments {
+            assert_eq!(factory.address, H160::from_str(address)?);
+            assert_eq!(factory.chain_id(), Some(chain_id));
+            assert_eq!(factory.fee, 300);
+        }
+
+        assert_eq!(
+            UniswapV2Factory::uniswap_v2_mainnet().creation_block,
+            UNISWAP_V2_CREATION_BLOCK_MAINNET
+        );
+        assert_eq!(
+            UNISWAP_V2_FACTORY_MAINNET,
+            UniswapV2Factory::uniswap_v2_mainnet().address
+        );
+
+        for (address, chain_id) in [
+            ("0x8909Dc15e40173Ff4699343b6eB8132c65e18eC6", 8453),
+            ("0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac", 1),
+        ] {
+            let factory = UniswapV2Factory::new(H160::from_str(address)?, 0, 300);
+            assert_eq!(factory.chain_id(), Some(chain_id));
+        }
+        assert_eq!(
+            UniswapV2Factory::new(H160::from_low_u64_be(1), 0, 300).chain_id(),
+            None
+        );
+
+        Ok(())
+    }
 
     #[test]
     fn test_swap_calldata() -> eyre::Result<()> {
@@ -567,6 +1366,72 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_mint_calldata() -> eyre::Result<()> {
+        let pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(0xa),
+            token_b: H160::from_low_u64_be(0xb),
+            ..Default::default()
+        };
+
+        let calldata = pool.mint_calldata(
+            U256::from(1000),
+            U256::from(2000),
+            U256::from(900),
+            U256::from(1800),
+            H160::from_low_u64_be(0xc),
+        )?;
+
+        let word = |value: &str| format!("{value:0>64}");
+        let expected = [
+            "e8e33700",
+            &word("a"),
+            &word("b"),
+            &word("3e8"),
+            &word("7d0"),
+            &word("384"),
+            &word("708"),
+            &word("c"),
+            &"f".repeat(64),
+        ]
+        .concat();
+        assert_eq!(ethers::utils::hex::encode(&calldata), expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_burn_calldata() -> eyre::Result<()> {
+        let pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(0xa),
+            token_b: H160::from_low_u64_be(0xb),
+            ..Default::default()
+        };
+
+        let calldata = pool.burn_calldata(
+            U256::from(500),
+            U256::from(100),
+            U256::from(200),
+            H160::from_low_u64_be(0xc),
+        )?;
+
+        let word = |value: &str| format!("{value:0>64}");
+        let expected = [
+            "baa2abde",
+            &word("a"),
+            &word("b"),
+            &word("1f4"),
+            &word("64"),
+            &word("c8"),
+            &word("c"),
+            &"f".repeat(64),
+        ]
+        .concat();
+        assert_eq!(ethers::utils::hex::encode(&calldata), expected);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_get_new_from_address() -> eyre::Result<()> {
         let rpc_endpoint = std::env::var("ETHEREUM_RPC_ENDPOINT")?;
@@ -641,6 +1506,7 @@ mod tests {
             reserve_0: 23595096345912178729927,
             reserve_1: 154664232014390554564,
             fee: 300,
+            ..Default::default()
         };
 
         assert!(x.calculate_price(token_a)? != 0.0);
@@ -648,6 +1514,792 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_sanity_check() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let mut pool = UniswapV2Pool {
+            address: H160::from_low_u64_be(3),
+            token_a,
+            token_a_decimals: 6,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0: 47092140895915,
+            reserve_1: 28396598565590008529300,
+            fee: 300,
+            ..Default::default()
+        };
+
+        assert!(pool.sanity_check().is_empty());
+
+        pool.reserve_0 = U112_MAX + 1;
+        pool.token_b_decimals = 40;
+        pool.fee = 20000;
+        pool.token_b = token_a;
+        pool.address = token_a;
+
+        assert_eq!(
+            pool.sanity_check(),
+            vec![
+                SanityIssue::ReservesOutOfRange,
+                SanityIssue::DecimalsUnusual(40),
+                SanityIssue::FeeOutOfRange(20000),
+                SanityIssue::TokensSameAddress,
+                SanityIssue::AddressMismatch,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sync_from_log_reserves_overflow() {
+        let mut pool = UniswapV2Pool {
+            address: H160::from_low_u64_be(1),
+            reserve_0: 100,
+            reserve_1: 100,
+            ..Default::default()
+        };
+
+        let sync_log = |reserve_0: u128, reserve_1: u128| Log {
+            address: pool.address,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: ethers::abi::encode(&[
+                Token::Uint(U256::from(reserve_0)),
+                Token::Uint(U256::from(reserve_1)),
+            ])
+            .into(),
+            ..Default::default()
+        };
+
+        let overflow_log = sync_log(U112_MAX + 1, 200);
+        let valid_log = sync_log(U112_MAX, 200);
+
+        assert!(matches!(
+            pool.sync_from_log(overflow_log),
+            Err(EventLogError::ReservesOverflow(address)) if address == pool.address
+        ));
+        assert_eq!(pool.reserve_0, 100);
+
+        pool.sync_from_log(valid_log).unwrap();
+        assert_eq!(pool.reserve_0, U112_MAX);
+        assert_eq!(pool.reserve_1, 200);
+    }
+
+    #[test]
+    fn test_reserve_velocity() {
+        let address = H160::from_low_u64_be(1);
+        let mut pool = UniswapV2Pool {
+            address,
+            ..Default::default()
+        };
+
+        let sync_log = |block_number: u64, reserve_0: u128, reserve_1: u128| Log {
+            address,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: ethers::abi::encode(&[
+                Token::Uint(U256::from(reserve_0)),
+                Token::Uint(U256::from(reserve_1)),
+            ])
+            .into(),
+            block_number: Some(block_number.into()),
+            ..Default::default()
+        };
+
+        pool.sync_from_log(sync_log(99, 900, 900)).unwrap();
+        assert_eq!(pool.reserve_velocity(), None);
+
+        pool.track_reserves(3);
+        pool.sync_from_log(sync_log(100, 1000, 1000)).unwrap();
+        assert_eq!(pool.reserve_velocity(), None);
+
+        pool.sync_from_log(sync_log(102, 1100, 950)).unwrap();
+        pool.sync_from_log(sync_log(105, 1050, 1000)).unwrap();
+        assert_eq!(pool.reserve_velocity(), Some(50.0));
+
+        //The update at block 100 is evicted from the window
+        pool.sync_from_log(sync_log(106, 1050, 1010)).unwrap();
+        assert_eq!(pool.reserve_velocity(), Some(27.5));
+    }
+
+    #[test]
+    fn test_liquidity_at_price() {
+        let pool = UniswapV2Pool {
+            token_a_decimals: 6,
+            token_b_decimals: 18,
+            reserve_0: 47092140895915,
+            reserve_1: 28396598565590008529300,
+            ..Default::default()
+        };
+
+        let expected = (47092140895915.0 / 1e6 * 28396598565590008529300.0 / 1e18_f64).sqrt();
+        assert!((pool.liquidity_at_price() - expected).abs() / expected < 1e-12);
+
+        //The product of the raw reserves overflows a u128
+        let pool = UniswapV2Pool {
+            reserve_0: U112_MAX,
+            reserve_1: U112_MAX,
+            ..Default::default()
+        };
+        assert!((pool.liquidity_at_price() - U112_MAX as f64).abs() / (U112_MAX as f64) < 1e-12);
+    }
+
+    #[test]
+    fn test_price_confidence() {
+        let (token_a, token_b) = (H160::from_low_u64_be(1), H160::from_low_u64_be(2));
+
+        let min_reserve = 1_000_000;
+        let deep_pool =
+            UniswapV2Pool::test_pool(10, token_a, token_b, 1_000_000_000_000, 2_000_000_000_000);
+        let thin_pool =
+            UniswapV2Pool::test_pool(11, token_a, token_b, 2_000_000, 1_000_000_000_000);
+        let empty_pool = UniswapV2Pool::test_pool(12, token_a, token_b, 1_000_000, 1_000_000);
+
+        assert!(deep_pool.price_confidence(min_reserve) > thin_pool.price_confidence(min_reserve));
+        assert!(deep_pool.price_confidence(min_reserve) <= 1.0);
+        assert_eq!(thin_pool.price_confidence(min_reserve), 0.5);
+        assert_eq!(empty_pool.price_confidence(min_reserve), 0.0);
+    }
+
+    #[test]
+    fn test_total_value_locked() {
+        let usdc = H160::from_low_u64_be(1);
+        let weth = H160::from_low_u64_be(2);
+        let pool = UniswapV2Pool {
+            token_a: usdc,
+            token_a_decimals: 6,
+            token_b: weth,
+            token_b_decimals: 18,
+            reserve_0: 2_000_000_000_000,
+            reserve_1: 1_000_000_000_000_000_000_000,
+            ..Default::default()
+        };
+
+        assert_eq!(pool.total_value_locked(usdc).unwrap(), 4_000_000.0);
+        assert_eq!(pool.total_value_locked(weth).unwrap(), 2_000.0);
+
+        let dai = H160::from_low_u64_be(3);
+        assert!(matches!(
+            pool.total_value_locked(dai),
+            Err(ArithmeticError::InvalidToken(token)) if token == dai
+        ));
+    }
+
+    #[test]
+    fn test_to_sdk_json() -> eyre::Result<()> {
+        let pool = UniswapV2Pool {
+            address: H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc")?,
+            token_a: H160::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48")?,
+            token_a_decimals: 6,
+            token_b: H160::from_str("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2")?,
+            token_b_decimals: 18,
+            reserve_0: 47092140895915,
+            reserve_1: 28396598565590008529300,
+            ..Default::default()
+        };
+
+        let json = pool.to_sdk_json(1);
+
+        assert_eq!(
+            json["address"],
+            "0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc"
+        );
+
+        let token_a = &json["tokenAmountA"]["token"];
+        assert_eq!(token_a["chainId"], 1);
+        assert_eq!(
+            token_a["address"],
+            "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"
+        );
+        assert_eq!(token_a["decimals"], 6);
+        assert!(token_a["symbol"].is_null());
+        assert_eq!(json["tokenAmountA"]["amount"], "47092140895915");
+
+        let token_b = &json["tokenAmountB"]["token"];
+        assert_eq!(
+            token_b["address"],
+            "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"
+        );
+        assert_eq!(token_b["decimals"], 18);
+        assert_eq!(json["tokenAmountB"]["amount"], "28396598565590008529300");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulate_swap_reserves_overflow() {
+        assert_eq!(U112_MAX, 5192296858534827628530496329220095);
+        assert_eq!(U128_MAX, u128::MAX);
+        assert_eq!(U256(U256_U128_MAX.into_limbs()), U256::from(u128::MAX));
+
+        let mut pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: U112_MAX - 1000,
+            reserve_1: 1_000_000,
+            fee: 300,
+            ..Default::default()
+        };
+
+        assert!(pool.simulate_swap(pool.token_a, U256::from(1000)).is_ok());
+        assert!(matches!(
+            pool.simulate_swap(pool.token_a, U256::from(1001)),
+            Err(SwapSimulationError::ReservesOverflow)
+        ));
+        assert!(matches!(
+            pool.simulate_swap(pool.token_b, U256::MAX),
+            Err(SwapSimulationError::ReservesOverflow)
+        ));
+        assert!(matches!(
+            pool.simulate_swap_mut(pool.token_a, U256::from(u128::MAX) + 1),
+            Err(SwapSimulationError::ReservesOverflow)
+        ));
+        assert_eq!(pool.reserve_0, U112_MAX - 1000);
+
+        pool.simulate_swap_mut(pool.token_a, U256::from(1000))
+            .unwrap();
+        assert_eq!(pool.reserve_0, U112_MAX);
+    }
+
+    #[test]
+    fn test_simulate_swap_protocol_fee() {
+        let pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1_000_000_000,
+            reserve_1: 1_000_000_000,
+            fee: 300,
+            ..Default::default()
+        };
+        let mut split_pool = UniswapV2Pool {
+            protocol_fee_bps: 5,
+            ..pool.clone()
+        };
+        let mut pool = pool;
+        let amount_in = U256::from(10_000_000);
+
+        assert_eq!(split_pool.protocol_fee_amount(amount_in), U256::from(5000));
+        assert_eq!(pool.protocol_fee_amount(amount_in), U256::zero());
+
+        //The protocol fee is part of the swap fee, the first swap pays out the same amount
+        let amount_out = pool.simulate_swap_mut(pool.token_a, amount_in).unwrap();
+        assert_eq!(
+            split_pool
+                .simulate_swap_mut(split_pool.token_a, amount_in)
+                .unwrap(),
+            amount_out
+        );
+        assert_eq!(pool.reserve_0 - split_pool.reserve_0, 5000);
+        assert_eq!(pool.reserve_1, split_pool.reserve_1);
+
+        //Less of the input stays in the reserves, so the next swap in the same direction pays out more
+        assert!(
+            split_pool
+                .simulate_swap(split_pool.token_a, amount_in)
+                .unwrap()
+                > pool.simulate_swap(pool.token_a, amount_in).unwrap()
+        );
+
+        //The protocol fee is capped at the swap fee
+        split_pool.protocol_fee_bps = 100;
+        assert_eq!(split_pool.protocol_fee_amount(amount_in), U256::from(30000));
+    }
+
+    #[test]
+    fn test_liquidity_score() {
+        let pool = UniswapV2Pool {
+            reserve_0: 47092140895915,
+            reserve_1: 28396598565590008529300,
+            ..Default::default()
+        };
+
+        //Doubling both reserves quadruples the reserve product
+        let deeper_pool = UniswapV2Pool {
+            reserve_0: pool.reserve_0 * 2,
+            reserve_1: pool.reserve_1 * 2,
+            ..Default::default()
+        };
+
+        let score = pool.liquidity_score();
+        let deeper_score = deeper_pool.liquidity_score();
+
+        assert!(score > 0.0);
+        assert!((deeper_score / score - 2.0).abs() < 1e-9);
+        assert_eq!(UniswapV2Pool::default().liquidity_score(), 0.0);
+    }
+
+    #[test]
+    fn test_claimable_lp_tokens() {
+        assert_eq!(UniswapV2Pool::locked_lp_tokens(), 1000);
+        assert_eq!(
+            UniswapV2Pool::claimable_lp_tokens(U256::from(1000)),
+            U256::zero()
+        );
+        assert_eq!(
+            UniswapV2Pool::claimable_lp_tokens(U256::from(1500)),
+            U256::from(500)
+        );
+    }
+
+    #[test]
+    fn test_storage_overrides() {
+        let pool = UniswapV2Pool {
+            reserve_0: 47092140895915,
+            reserve_1: 28396598565590008529300,
+            ..Default::default()
+        };
+
+        let overrides = pool.storage_overrides();
+        assert_eq!(overrides.len(), 1);
+
+        let slot_value = U256::from_big_endian(
+            overrides[&H256::from_low_u64_be(RESERVES_STORAGE_SLOT)].as_bytes(),
+        );
+        let u112_mask = U256::from(U112_MAX);
+
+        assert_eq!((slot_value & u112_mask).as_u128(), pool.reserve_0);
+        assert_eq!(((slot_value >> 112) & u112_mask).as_u128(), pool.reserve_1);
+        assert!((slot_value >> 224).is_zero());
+    }
+
+    #[test]
+    fn test_simulate_swap_insufficient_liquidity() {
+        let token_a = H160::from_low_u64_be(1);
+        let mut pool = UniswapV2Pool {
+            token_a,
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1000,
+            reserve_1: 1000,
+            fee: 300,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            pool.simulate_swap(token_a, U256::zero()),
+            Err(SwapSimulationError::InsufficientLiquidity)
+        ));
+        assert!(matches!(
+            pool.simulate_swap_mut(token_a, U256::zero()),
+            Err(SwapSimulationError::InsufficientLiquidity)
+        ));
+
+        pool.reserve_1 = 0;
+        assert!(matches!(
+            pool.simulate_swap(token_a, U256::from(100)),
+            Err(SwapSimulationError::InsufficientLiquidity)
+        ));
+        assert!(matches!(
+            pool.simulate_swap_mut(token_a, U256::from(100)),
+            Err(SwapSimulationError::InsufficientLiquidity)
+        ));
+        assert_eq!(pool.reserve_0, 1000);
+    }
+
+    #[test]
+    fn test_round_trip_loss_bps() -> eyre::Result<()> {
+        let token_a = H160::from_low_u64_be(1);
+        let pool = UniswapV2Pool {
+            token_a,
+            token_a_decimals: 18,
+            token_b: H160::from_low_u64_be(2),
+            token_b_decimals: 18,
+            reserve_0: 1_000_000_000_000_000_000_000,
+            reserve_1: 1_000_000_000_000_000_000_000,
+            fee: 300,
+            ..Default::default()
+        };
+
+        //Fee of 300 => 30 bps per swap
+        let small_trade_loss =
+            pool.round_trip_loss_bps(token_a, U256::from(1_000_000_000_000_u64))?;
+        assert!((60..=61).contains(&small_trade_loss));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_empty_pool_from_log_creation_block() -> eyre::Result<()> {
+        let log = Log {
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(H160::from_low_u64_be(1)),
+                H256::from(H160::from_low_u64_be(2)),
+            ],
+            data: ethers::abi::encode(&[
+                Token::Address(H160::from_low_u64_be(3)),
+                Token::Uint(U256::one()),
+            ])
+            .into(),
+            block_number: Some(U64::from(10000835)),
+            ..Default::default()
+        };
+
+        let pool = UniswapV2Pool::new_empty_pool_from_log(log)?;
+
+        assert_eq!(pool.address, H160::from_low_u64_be(3));
+        assert_eq!(pool.creation_block, 10000835);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_log_and_fee() -> eyre::Result<()> {
+        let log = Log {
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(H160::from_low_u64_be(1)),
+                H256::from(H160::from_low_u64_be(2)),
+            ],
+            data: ethers::abi::encode(&[
+                Token::Address(H160::from_low_u64_be(3)),
+                Token::Uint(U256::one()),
+            ])
+            .into(),
+            block_number: Some(U64::from(10000835)),
+            ..Default::default()
+        };
+
+        let pool = UniswapV2Pool::from_log_and_fee(log, 300)?;
+
+        assert_eq!(pool.address, H160::from_low_u64_be(3));
+        assert_eq!(pool.token_a, H160::from_low_u64_be(1));
+        assert_eq!(pool.token_b, H160::from_low_u64_be(2));
+        assert_eq!(pool.fee, 300);
+        assert_eq!(pool.creation_block, 10000835);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_amount_out_fees() {
+        let reserve_in = U256::from(1_000_000_000_000_000_000_u128);
+        let reserve_out = U256::from(2_000_000_000_000_000_000_u128);
+        let amount_in = U256::from(1_000_000_000_000_000_u128);
+
+        for (fee, expected_amount_out) in [
+            (100, 1996005990015974_u128),
+            (250, 1993011970559367),
+            (300, 1992013962079806),
+            (500, 1988021918191399),
+            (1000, 1978041738678708),
+            (9000, 1818345305771747),
+        ] {
+            let pool = UniswapV2Pool {
+                fee,
+                ..Default::default()
+            };
+
+            assert_eq!(
+                pool.get_amount_out(amount_in, reserve_in, reserve_out),
+                U256::from(expected_amount_out),
+                "fee {fee}"
+            );
+        }
+
+        //Fees above 100% are rejected instead of underflowing
+        let pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1000,
+            reserve_1: 1000,
+            fee: 100010,
+            ..Default::default()
+        };
+
+        assert!(pool
+            .get_amount_out(amount_in, reserve_in, reserve_out)
+            .is_zero());
+        assert!(matches!(
+            pool.simulate_swap(pool.token_a, U256::from(10)),
+            Err(SwapSimulationError::InvalidFee(10001))
+        ));
+        assert!(matches!(
+            pool.clone().simulate_swap_mut(pool.token_b, U256::from(10)),
+            Err(SwapSimulationError::InvalidFee(10001))
+        ));
+    }
+
+    #[test]
+    fn test_fee_model() -> eyre::Result<()> {
+        let reserve_in = U256::from(1_000_000_000_000_000_000_u128);
+        let reserve_out = U256::from(2_000_000_000_000_000_000_u128);
+        let amount_in = U256::from(1_000_000_000_000_000_u128);
+
+        //Matches the default math for a fee of 300
+        let pool = UniswapV2Pool {
+            fee_model: Some(FeeModel::UniswapV2 { bps: 30 }),
+            ..Default::default()
+        };
+        assert_eq!(
+            pool.get_amount_out(amount_in, reserve_in, reserve_out),
+            U256::from(1992013962079806_u128)
+        );
+        assert_eq!(pool.fee_bps(), 30);
+
+        //The model survives a round trip through a checkpoint
+        let deserialized_pool: UniswapV2Pool =
+            serde_json::from_str(&serde_json::to_string(&pool)?)?;
+        assert!(matches!(
+            deserialized_pool.fee_model,
+            Some(FeeModel::UniswapV2 { bps: 30 })
+        ));
+
+        //A fork taking a 17 bps fee over a base of 1,000,000
+        fn amount_out_17_bps(amount_in: U256, reserve_in: U256, reserve_out: U256) -> U256 {
+            let amount_in_with_fee = amount_in * U256::from(998300);
+            amount_in_with_fee * reserve_out
+                / (reserve_in * U256::from(1_000_000) + amount_in_with_fee)
+        }
+
+        let factory = UniswapV2Factory::new(H160::from_low_u64_be(100), 0, 300)
+            .with_fee_model(FeeModel::Custom(amount_out_17_bps));
+
+        let log = Log {
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(H160::from_low_u64_be(1)),
+                H256::from(H160::from_low_u64_be(2)),
+            ],
+            data: ethers::abi::encode(&[
+                Token::Address(H160::from_low_u64_be(3)),
+                Token::Uint(U256::one()),
+            ])
+            .into(),
+            ..Default::default()
+        };
+
+        let AMM::UniswapV2Pool(pool) = factory.new_empty_amm_from_log(log)? else {
+            panic!("Expected a UniswapV2Pool");
+        };
+
+        assert_eq!(pool.factory, factory.address);
+        assert_eq!(
+            pool.get_amount_out(amount_in, reserve_in, reserve_out),
+            U256::from(1994608782052876_u128)
+        );
+
+        //Custom models are rejected rather than silently dropped
+        assert!(serde_json::to_string(&pool).is_err());
+        assert!(serde_json::to_string(&factory).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fee_model_drains_reserves() {
+        fn amount_out_all(_: U256, _: U256, reserve_out: U256) -> U256 {
+            reserve_out
+        }
+
+        let mut pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1000,
+            reserve_1: 1000,
+            fee_model: Some(FeeModel::Custom(amount_out_all)),
+            ..Default::default()
+        };
+
+        for token_in in [pool.token_a, pool.token_b] {
+            assert!(matches!(
+                pool.simulate_swap(token_in, U256::from(10)),
+                Err(SwapSimulationError::InsufficientLiquidity)
+            ));
+            assert!(matches!(
+                pool.simulate_swap_mut(token_in, U256::from(10)),
+                Err(SwapSimulationError::InsufficientLiquidity)
+            ));
+        }
+
+        assert_eq!((pool.reserve_0, pool.reserve_1), (1000, 1000));
+    }
+
+    #[test]
+    fn test_calculate_liquidity_provision() {
+        let ether = U256::exp10(18);
+        let mut pool = UniswapV2Pool::default();
+
+        //The initial provision mints the geometric mean of the amounts, minus the locked minimum liquidity
+        assert_eq!(
+            pool.calculate_liquidity_provision(ether, ether * 4, U256::zero()),
+            ether * 2 - 1000
+        );
+        assert_eq!(
+            pool.calculate_liquidity_provision(U256::from(1000), U256::from(1000), U256::zero()),
+            U256::zero()
+        );
+
+        pool.reserve_0 = 2_000_000_000_000_000_000;
+        pool.reserve_1 = 8_000_000_000_000_000_000;
+        let total_supply = ether * 4;
+
+        assert_eq!(
+            pool.calculate_liquidity_provision(ether, ether * 4, total_supply),
+            ether * 2
+        );
+        //Unbalanced deposits are credited for the smaller share
+        assert_eq!(
+            pool.calculate_liquidity_provision(ether, ether * 2, total_supply),
+            ether
+        );
+    }
+
+    #[test]
+    fn test_depth_chart() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let pool = UniswapV2Pool {
+            token_a,
+            token_a_decimals: 18,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0: 1_000_000_000_000_000_000_000,
+            reserve_1: 2_000_000_000_000_000_000_000,
+            fee: 300,
+            ..Default::default()
+        };
+
+        let chart = pool.depth_chart(token_a, 10, 50);
+        assert_eq!(chart.len(), 10);
+
+        //A 0.5% step above a price of 2
+        assert!((chart[0].0 - 2.01).abs() < 1e-9);
+        assert!(chart
+            .windows(2)
+            .all(|levels| levels[1].0 > levels[0].0 && levels[1].1 > levels[0].1));
+        assert!(chart[9].1 < U256::from(pool.reserve_0));
+
+        //Buying the cumulative amount moves the pool price to the level price
+        let mut swapped = pool.clone();
+        swapped.reserve_0 -= chart[4].1.as_u128();
+        swapped.reserve_1 = (U256::from(pool.reserve_0) * U256::from(pool.reserve_1)
+            / U256::from(swapped.reserve_0))
+        .as_u128();
+        let price = swapped.calculate_price(token_a).unwrap();
+        assert!((price - chart[4].0).abs() / chart[4].0 < 1e-9);
+
+        assert!(pool
+            .depth_chart(H160::from_low_u64_be(3), 10, 50)
+            .is_empty());
+
+        let oversized = UniswapV2Pool {
+            reserve_0: u128::MAX,
+            ..pool.clone()
+        };
+        assert!(oversized.depth_chart(token_a, 10, 50).is_empty());
+        assert!(UniswapV2Pool::default()
+            .depth_chart(H160::zero(), 10, 50)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_checksum_address() -> eyre::Result<()> {
+        let pool = UniswapV2Pool {
+            address: H160::from_str("0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc")?,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            pool.checksum_address(),
+            "0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_calculate_price_invalid_token() {
+        let unrelated_token = H160::from_low_u64_be(3);
+        let pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_a_decimals: 18,
+            token_b: H160::from_low_u64_be(2),
+            token_b_decimals: 18,
+            reserve_0: 1_000_000,
+            reserve_1: 2_000_000,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            pool.calculate_price(unrelated_token),
+            Err(ArithmeticError::InvalidToken(token)) if token == unrelated_token
+        ));
+        assert!(matches!(
+            pool.calculate_price_64_x_64(unrelated_token),
+            Err(ArithmeticError::InvalidToken(token)) if token == unrelated_token
+        ));
+        assert!(pool.calculate_price(pool.token_b).is_ok());
+    }
+
+    #[test]
+    fn test_decimal_shift() -> eyre::Result<()> {
+        let mut pool = UniswapV2Pool {
+            token_a: H160::from_low_u64_be(1),
+            token_a_decimals: 6,
+            token_b: H160::from_low_u64_be(2),
+            token_b_decimals: 18,
+            reserve_0: 1_000_000_000,
+            reserve_1: 1_000_000_000_000_000_000_000,
+            ..Default::default()
+        };
+
+        assert_eq!(pool.decimal_shift(), -12);
+        let price = pool.calculate_price(pool.token_a)?;
+
+        pool.token_b_decimals = 6;
+        pool.reserve_1 = 1_000_000_000;
+
+        assert_eq!(pool.decimal_shift(), 0);
+        assert_eq!(pool.calculate_price(pool.token_a)?, price);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserves_u256() {
+        let pool = UniswapV2Pool {
+            reserve_0: 47092140895915,
+            reserve_1: u128::MAX,
+            ..Default::default()
+        };
+
+        assert_eq!(pool.reserve_0_u256(), U256::from(47092140895915_u128));
+        assert_eq!(pool.reserve_1_u256(), U256::from(u128::MAX));
+        assert_eq!(pool.reserve_1_u256().as_u128(), pool.reserve_1);
+    }
+
+    #[test]
+    fn test_calculate_price_with_fee() -> eyre::Result<()> {
+        let token_a = H160::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48")?;
+        let token_b = H160::from_str("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2")?;
+        let mut pool = UniswapV2Pool {
+            address: H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc")?,
+            token_a,
+            token_a_decimals: 6,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0: 47092140895915,
+            reserve_1: 28396598565590008529300,
+            fee: 300,
+            ..Default::default()
+        };
+
+        for base_token in [token_a, token_b] {
+            let spot_price = pool.calculate_price(base_token)?;
+            let price_with_fee = pool.calculate_price_with_fee(base_token)?;
+
+            assert!(price_with_fee < spot_price);
+            assert!((price_with_fee - spot_price * 0.997).abs() <= spot_price * 1e-12);
+        }
+
+        pool.fee = 0;
+        assert_eq!(
+            pool.calculate_price_with_fee(token_a)?,
+            pool.calculate_price(token_a)?
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_calculate_price() -> eyre::Result<()> {
         let rpc_endpoint = std::env::var("ETHEREUM_RPC_ENDPOINT")?;
@@ -696,4 +2348,312 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_assert_parity_with_router() -> eyre::Result<()> {
+        let router = H160::from_str("0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D")?;
+        let pool = UniswapV2Pool {
+            address: H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc")?,
+            token_a: H160::from_str("0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48")?,
+            token_a_decimals: 6,
+            token_b: H160::from_str("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2")?,
+            token_b_decimals: 18,
+            reserve_0: 47092140895915,
+            reserve_1: 28396598565590008529300,
+            fee: 300,
+            ..Default::default()
+        };
+
+        let amount_in = U256::from(1_000_000_000);
+        let amount_out = pool.simulate_swap(pool.token_a, amount_in)?;
+
+        let router_response = |amount_out: U256| {
+            Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+                Token::Uint(amount_in),
+                Token::Uint(amount_out),
+            ])]))
+        };
+
+        let (middleware, mock) = Provider::mocked();
+        let middleware = Arc::new(middleware);
+
+        mock.push::<Bytes, _>(router_response(amount_out))?;
+        pool.assert_parity_with_router(router, amount_in, middleware.clone())
+            .await?;
+
+        mock.push::<Bytes, _>(router_response(amount_out - 1))?;
+        match pool
+            .assert_parity_with_router(router, amount_in, middleware.clone())
+            .await
+        {
+            Err(AMMError::RouterParityMismatch(
+                mismatch_router,
+                router_amount_out,
+                local_amount_out,
+            )) => {
+                assert_eq!(mismatch_router, router);
+                assert_eq!(router_amount_out, amount_out - 1);
+                assert_eq!(local_amount_out, amount_out);
+            }
+            _ => panic!("Expected a router parity mismatch"),
+        }
+
+        Ok(())
+    }
+
+    fn mocked_pool_data(token_a: H160, token_b: H160) -> Bytes {
+        Bytes::from(ethers::abi::encode(&[Token::Array(vec![Token::Tuple(
+            vec![
+                Token::Address(token_a),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_b),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1_000_000_000_000_u64)),
+                Token::Uint(U256::from(2_000_000_000_000_u64)),
+            ],
+        )])]))
+    }
+
+    fn mocked_error(message: &str) -> MockResponse {
+        MockResponse::Error(JsonRpcError {
+            code: 3,
+            message: message.to_string(),
+            data: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_populate_data_k_last() -> eyre::Result<()> {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let k_last = U256::from(1_000_000_000_000_u64) * U256::from(2_000_000_000_000_u64);
+        let pool_data = mocked_pool_data(token_a, token_b);
+
+        //Mocked responses are returned in reverse order, the standard pair does not implement `fee()`
+        let (middleware, mock) = Provider::mocked();
+        mock.push_response(mocked_error("execution reverted"));
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(k_last)])))?;
+        mock.push::<Bytes, _>(pool_data.clone())?;
+
+        let middleware = Arc::new(middleware);
+
+        let mut pool = UniswapV2Pool {
+            address: H160::from_low_u64_be(10),
+            fee: 300,
+            ..Default::default()
+        };
+        pool.populate_data(None, middleware.clone()).await?;
+
+        assert_eq!(pool.token_a, token_a);
+        assert_eq!(pool.reserve_1, 2_000_000_000_000);
+        assert_eq!(pool.k_last, k_last);
+        assert_eq!(pool.fee, 300);
+
+        //Pairs without `kLast` revert
+        mock.push_response(mocked_error("execution reverted"));
+        mock.push_response(mocked_error("execution reverted"));
+        mock.push::<Bytes, _>(pool_data.clone())?;
+        pool.populate_data(None, middleware.clone()).await?;
+        assert_eq!(pool.k_last, U256::zero());
+
+        //Other errors are not mistaken for a missing `kLast`
+        mock.push_response(mocked_error("rate limit exceeded"));
+        mock.push::<Bytes, _>(pool_data)?;
+        assert!(pool.populate_data(None, middleware).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_populate_data_reserves_overflow() -> eyre::Result<()> {
+        let pool_data = Bytes::from(ethers::abi::encode(&[Token::Array(vec![Token::Tuple(
+            vec![
+                Token::Address(H160::from_low_u64_be(1)),
+                Token::Uint(U256::from(18)),
+                Token::Address(H160::from_low_u64_be(2)),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(U112_MAX) + 1),
+                Token::Uint(U256::from(1000)),
+            ],
+        )])]));
+
+        let (middleware, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(pool_data)?;
+
+        let mut pool = UniswapV2Pool {
+            address: H160::from_low_u64_be(10),
+            reserve_0: 500,
+            reserve_1: 600,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            pool.populate_data(None, Arc::new(middleware)).await,
+            Err(AMMError::ReservesOverflow(address)) if address == pool.address
+        ));
+        //The rejected reserves are not applied
+        assert!(pool.token_a.is_zero());
+        assert_eq!((pool.reserve_0, pool.reserve_1), (500, 600));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_amm_data_batch_request_reserves_overflow() -> eyre::Result<()> {
+        let pool_data = |reserve_0: U256| {
+            Token::Tuple(vec![
+                Token::Address(H160::from_low_u64_be(1)),
+                Token::Uint(U256::from(18)),
+                Token::Address(H160::from_low_u64_be(2)),
+                Token::Uint(U256::from(18)),
+                Token::Uint(reserve_0),
+                Token::Uint(U256::from(1000)),
+            ])
+        };
+
+        let (middleware, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            pool_data(U256::from(U112_MAX) + 1),
+            pool_data(U256::from(500)),
+            pool_data(U256::MAX),
+        ])])))?;
+
+        let mut amms: Vec<AMM> = (10..13)
+            .map(|address| {
+                AMM::UniswapV2Pool(UniswapV2Pool {
+                    address: H160::from_low_u64_be(address),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        batch_request::get_amm_data_batch_request(&mut amms, Arc::new(middleware)).await?;
+
+        //Pools with oversized reserves are left unpopulated instead of panicking
+        assert_eq!(
+            amms.iter().map(|amm| amm.reserves()).collect::<Vec<_>>(),
+            vec![vec![0, 0], vec![500, 1000], vec![0, 0]]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_populate_data_pair_fee() -> eyre::Result<()> {
+        let pool_data = mocked_pool_data(H160::from_low_u64_be(1), H160::from_low_u64_be(2));
+        let k_last = Bytes::from(ethers::abi::encode(&[Token::Uint(U256::zero())]));
+        let pair_fee = |fee: u32| Bytes::from(ethers::abi::encode(&[Token::Uint(U256::from(fee))]));
+
+        //Mocked responses are returned in reverse order
+        let (middleware, mock) = Provider::mocked();
+        let middleware = Arc::new(middleware);
+
+        let mut pool = UniswapV2Pool {
+            address: H160::from_low_u64_be(10),
+            fee: 300,
+            ..Default::default()
+        };
+
+        //The pair fee replaces the factory fee
+        mock.push::<Bytes, _>(pair_fee(500))?;
+        mock.push::<Bytes, _>(k_last.clone())?;
+        mock.push::<Bytes, _>(pool_data.clone())?;
+        pool.populate_data(None, middleware.clone()).await?;
+        assert_eq!(pool.fee, 500);
+
+        //A fee of 100% or more is not a fee in the expected unit
+        mock.push::<Bytes, _>(pair_fee(100_000))?;
+        mock.push::<Bytes, _>(k_last.clone())?;
+        mock.push::<Bytes, _>(pool_data.clone())?;
+        assert!(matches!(
+            pool.populate_data(None, middleware.clone()).await,
+            Err(AMMError::InvalidPairFee(_, 100_000))
+        ));
+
+        //Errors other than a revert are propagated instead of falling back to the factory fee
+        mock.push_response(mocked_error("header not found"));
+        mock.push::<Bytes, _>(k_last)?;
+        mock.push::<Bytes, _>(pool_data)?;
+        assert!(pool.populate_data(None, middleware.clone()).await.is_err());
+
+        let factory = UniswapV2Factory::new(H160::from_low_u64_be(100), 0, 300);
+        let (token_a, token_b) = (H160::from_low_u64_be(1), H160::from_low_u64_be(2));
+        let pair = Bytes::from(ethers::abi::encode(&[Token::Address(pool.address)]));
+
+        //The factory fee is returned for pairs without `fee()`, but not cached
+        mock.push_response(mocked_error("execution reverted"));
+        mock.push::<Bytes, _>(pair.clone())?;
+        assert_eq!(
+            factory
+                .fee_for_pair(token_a, token_b, middleware.clone())
+                .await?,
+            300
+        );
+        assert!(factory.pair_fees.read().await.is_empty());
+
+        mock.push::<Bytes, _>(pair_fee(250))?;
+        mock.push::<Bytes, _>(pair)?;
+        assert_eq!(
+            factory
+                .fee_for_pair(token_b, token_a, middleware.clone())
+                .await?,
+            250
+        );
+        //Served from the cache
+        assert_eq!(
+            factory.fee_for_pair(token_a, token_b, middleware).await?,
+            250
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_conservative_amount_out() -> eyre::Result<()> {
+        let reserves = |reserve_0: u128, reserve_1: u128| {
+            Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::from(reserve_0)),
+                Token::Uint(U256::from(reserve_1)),
+                Token::Uint(U256::zero()),
+            ]))
+        };
+
+        //Mocked responses are returned in reverse order, the second block has the manipulated reserves
+        let (middleware, mock) = Provider::mocked();
+        mock.push::<Bytes, _>(reserves(1_000_000, 2_000_000))?;
+        mock.push::<Bytes, _>(reserves(1_500_000, 1_400_000))?;
+        mock.push::<Bytes, _>(reserves(1_000_000, 2_000_000))?;
+
+        let pool = UniswapV2Pool {
+            address: H160::from_low_u64_be(10),
+            token_a: H160::from_low_u64_be(1),
+            token_b: H160::from_low_u64_be(2),
+            reserve_0: 1_000_000,
+            reserve_1: 2_000_000,
+            fee: 300,
+            ..Default::default()
+        };
+        let amount_in = U256::from(10_000);
+
+        let manipulated_pool = UniswapV2Pool {
+            reserve_0: 1_500_000,
+            reserve_1: 1_400_000,
+            ..pool.clone()
+        };
+        let expected = manipulated_pool.simulate_swap(pool.token_a, amount_in)?;
+
+        let amount_out = pool
+            .conservative_amount_out(
+                pool.token_a,
+                amount_in,
+                &[100, 101, 102],
+                Arc::new(middleware),
+            )
+            .await?;
+
+        assert_eq!(amount_out, expected);
+        assert!(amount_out < pool.simulate_swap(pool.token_a, amount_in)?);
+
+        Ok(())
+    }
 }
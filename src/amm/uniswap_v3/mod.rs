@@ -2,7 +2,7 @@ pub mod batch_request;
 pub mod factory;
 
 use crate::{
-    amm::AutomatedMarketMaker,
+    amm::{AmmState, AutomatedMarketMaker, SanityIssue, MAX_TOKEN_DECIMALS},
     errors::{AMMError, ArithmeticError, EventLogError, SwapSimulationError},
 };
 use async_trait::async_trait;
@@ -157,6 +157,12 @@ impl AutomatedMarketMaker for UniswapV3Pool {
         vec![self.token_a, self.token_b]
     }
 
+    //Virtual reserves of the active liquidity, see `calculate_virtual_reserves`
+    fn reserves(&self) -> Vec<u128> {
+        let (reserve_0, reserve_1) = self.calculate_virtual_reserves().unwrap_or_default();
+        vec![reserve_0, reserve_1]
+    }
+
     fn calculate_price(&self, base_token: H160) -> Result<f64, ArithmeticError> {
         let tick = uniswap_v3_math::tick_math::get_tick_at_sqrt_ratio(self.sqrt_price)?;
         let shift = self.token_a_decimals as i8 - self.token_b_decimals as i8;
@@ -464,6 +470,83 @@ impl AutomatedMarketMaker for UniswapV3Pool {
             self.token_a
         }
     }
+
+    fn state_snapshot(&self) -> AmmState {
+        AmmState::UniswapV3Pool {
+            liquidity: self.liquidity,
+            sqrt_price: self.sqrt_price,
+            tick: self.tick,
+        }
+    }
+
+    fn restore_state(&mut self, state: AmmState) {
+        if let AmmState::UniswapV3Pool {
+            liquidity,
+            sqrt_price,
+            tick,
+        } = state
+        {
+            self.liquidity = liquidity;
+            self.sqrt_price = sqrt_price;
+            self.tick = tick;
+        }
+    }
+
+    //Fee of 500 => 0.05% => 5 bps
+    fn fee_bps(&self) -> u32 {
+        self.fee / 100
+    }
+
+    //Computed from the virtual reserves of the active liquidity, see `calculate_virtual_reserves`
+    fn total_value_locked(&self, base_token: H160) -> Result<f64, ArithmeticError> {
+        let (reserve_0, reserve_1) = self.calculate_virtual_reserves()?;
+
+        let (reserve, decimals) = if base_token == self.token_a {
+            (reserve_0, self.token_a_decimals)
+        } else if base_token == self.token_b {
+            (reserve_1, self.token_b_decimals)
+        } else {
+            return Err(ArithmeticError::InvalidToken(base_token));
+        };
+
+        Ok(2.0 * reserve as f64 / 10_f64.powi(decimals as i32))
+    }
+
+    //Hash map allocations are estimated from their capacity, ignoring the control bytes
+    fn memory_size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.tick_bitmap.capacity() * std::mem::size_of::<(i16, U256)>()
+            + self.ticks.capacity() * std::mem::size_of::<(i32, Info)>()
+    }
+
+    fn sanity_check(&self) -> Vec<SanityIssue> {
+        let mut issues = vec![];
+
+        if self.sqrt_price < MIN_SQRT_RATIO || self.sqrt_price >= MAX_SQRT_RATIO {
+            issues.push(SanityIssue::ReservesOutOfRange);
+        }
+
+        for decimals in [self.token_a_decimals, self.token_b_decimals] {
+            if decimals > MAX_TOKEN_DECIMALS {
+                issues.push(SanityIssue::DecimalsUnusual(decimals));
+            }
+        }
+
+        //Fee of 500 => 0.05%, anything at or above 100% is invalid
+        if self.fee >= 1_000_000 {
+            issues.push(SanityIssue::FeeOutOfRange(self.fee));
+        }
+
+        if self.token_a == self.token_b {
+            issues.push(SanityIssue::TokensSameAddress);
+        }
+
+        if self.address == self.token_a || self.address == self.token_b {
+            issues.push(SanityIssue::AddressMismatch);
+        }
+
+        issues
+    }
 }
 
 impl UniswapV3Pool {
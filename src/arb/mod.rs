@@ -0,0 +1,191 @@
+use ethers::types::{H160, U256, U512};
+
+use crate::{
+    amm::{uniswap_v2::UniswapV2Pool, AutomatedMarketMaker},
+    errors::ArithmeticError,
+};
+
+/// Returns the amount of `token` to swap through `pool_a` and back through `pool_b` that maximizes the arbitrage profit.
+///
+/// Returns zero if there is no profitable arbitrage, if `token` is not in both pools, if a pool fee exceeds 100% or if
+/// the computation overflows.
+pub fn optimal_arbitrage_amount(
+    pool_a: &UniswapV2Pool,
+    pool_b: &UniswapV2Pool,
+    token: H160,
+) -> U256 {
+    optimal_arb_amount(pool_a, pool_b, token).unwrap_or_default()
+}
+
+/// Returns the amount of `token_in` to swap through `pool_a` and back through `pool_b` that maximizes the arbitrage profit,
+/// i.e. selling `token_in` on the pool where it is dearer and buying it back on the pool where it is cheaper.
+///
+/// Composing the two swaps yields `amount_out = A * x / (B + C * x)`, which is maximized at `x = (sqrt(A * B) - B) / C`, accounting for the fee of both pools.
+/// Returns zero if there is no profitable arbitrage.
+pub fn optimal_arb_amount(
+    pool_a: &UniswapV2Pool,
+    pool_b: &UniswapV2Pool,
+    token_in: H160,
+) -> Result<U256, ArithmeticError> {
+    let (reserve_a_in, reserve_a_out) =
+        reserves_for(pool_a, token_in).ok_or(ArithmeticError::InvalidToken(token_in))?;
+
+    //The second swap goes from the token out of `pool_a` back to `token_in`
+    let (reserve_b_out, reserve_b_in) =
+        reserves_for(pool_b, token_in).ok_or(ArithmeticError::InvalidToken(token_in))?;
+
+    //Fee of 300 => 10,000 - 30 = 9970, over a base of 10,000
+    let fee_a = U512::from(fee_multiplier(pool_a)?);
+    let fee_b = U512::from(fee_multiplier(pool_b)?);
+
+    let a = fee_a * fee_b * reserve_a_out * reserve_b_out;
+    let b = U512::from(100_000_000) * reserve_a_in * reserve_b_in;
+    let c = fee_a * (U512::from(10000) * reserve_b_in + fee_b * reserve_a_out);
+
+    //The marginal rate of the composed swap must exceed 1 for the arbitrage to be profitable
+    if a <= b || c.is_zero() {
+        return Ok(U256::zero());
+    }
+
+    let ab = a.checked_mul(b).ok_or(ArithmeticError::Overflow)?;
+
+    U256::try_from((ab.integer_sqrt() - b) / c).map_err(|_| ArithmeticError::Overflow)
+}
+
+//Returns `10000 - fee_bps` of the pool, fees above 100% are invalid
+fn fee_multiplier(pool: &UniswapV2Pool) -> Result<u32, ArithmeticError> {
+    10000_u32
+        .checked_sub(pool.fee_bps())
+        .ok_or(ArithmeticError::InvalidFee(pool.fee_bps()))
+}
+
+//Returns the reserves of the pool as (reserve of `token`, reserve of the other token)
+fn reserves_for(pool: &UniswapV2Pool, token: H160) -> Option<(U512, U512)> {
+    if pool.token_a == token {
+        Some((U512::from(pool.reserve_0), U512::from(pool.reserve_1)))
+    } else if pool.token_b == token {
+        Some((U512::from(pool.reserve_1), U512::from(pool.reserve_0)))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{H160, U256};
+
+    use crate::{
+        amm::{uniswap_v2::UniswapV2Pool, AutomatedMarketMaker},
+        errors::ArithmeticError,
+    };
+
+    use super::{optimal_arb_amount, optimal_arbitrage_amount};
+
+    #[test]
+    fn test_optimal_arbitrage_amount() {
+        let token = H160::from_low_u64_be(1);
+
+        //Token a is worth 2 token b in pool a and 1.5 token b in pool b
+        let token_out = H160::from_low_u64_be(2);
+        let pool_a = UniswapV2Pool::test_pool(
+            10,
+            token,
+            token_out,
+            1_000_000_000_000_000_000_000,
+            2_000_000_000_000_000_000_000,
+        );
+        let pool_b = UniswapV2Pool::test_pool(
+            11,
+            token,
+            token_out,
+            1_000_000_000_000_000_000_000,
+            1_500_000_000_000_000_000_000,
+        );
+
+        let amount = optimal_arbitrage_amount(&pool_a, &pool_b, token);
+        assert!(amount > U256::zero());
+
+        //Selling on the cheaper pool is never profitable
+        assert_eq!(
+            optimal_arbitrage_amount(&pool_b, &pool_a, token),
+            U256::zero()
+        );
+
+        //Pools at equilibrium
+        assert_eq!(
+            optimal_arbitrage_amount(&pool_a, &pool_a.clone(), token),
+            U256::zero()
+        );
+
+        //Token not in the pools
+        assert_eq!(
+            optimal_arbitrage_amount(&pool_a, &pool_b, H160::from_low_u64_be(3)),
+            U256::zero()
+        );
+
+        //Fee over 100%
+        let pool_c = UniswapV2Pool {
+            fee: 200_000,
+            ..pool_b.clone()
+        };
+        assert_eq!(
+            optimal_arbitrage_amount(&pool_a, &pool_c, token),
+            U256::zero()
+        );
+    }
+
+    #[test]
+    fn test_optimal_arb_amount() -> eyre::Result<()> {
+        let token_in = H160::from_low_u64_be(1);
+        let token_out = H160::from_low_u64_be(2);
+        let pool_a = UniswapV2Pool::test_pool(
+            10,
+            token_in,
+            token_out,
+            1_000_000_000_000_000_000_000,
+            2_000_000_000_000_000_000_000,
+        );
+        let pool_b = UniswapV2Pool::test_pool(
+            11,
+            token_in,
+            token_out,
+            1_000_000_000_000_000_000_000,
+            1_500_000_000_000_000_000_000,
+        );
+
+        //Profit of swapping `amount_in` through pool a and back through pool b
+        let profit = |amount_in: U256| -> eyre::Result<i128> {
+            let amount_out = pool_a.simulate_swap(token_in, amount_in)?;
+            let amount_out = pool_b.simulate_swap(pool_a.token_b, amount_out)?;
+            Ok(amount_out.as_u128() as i128 - amount_in.as_u128() as i128)
+        };
+
+        let amount = optimal_arb_amount(&pool_a, &pool_b, token_in)?;
+        let optimal_profit = profit(amount)?;
+
+        assert!(amount > U256::zero());
+        assert!(optimal_profit > 0);
+        assert!(profit(amount * 99 / 100)? < optimal_profit);
+        assert!(profit(amount * 101 / 100)? < optimal_profit);
+
+        assert_eq!(
+            optimal_arb_amount(&pool_b, &pool_a, token_in)?,
+            U256::zero()
+        );
+        assert!(matches!(
+            optimal_arb_amount(&pool_a, &pool_b, H160::from_low_u64_be(3)),
+            Err(ArithmeticError::InvalidToken(_))
+        ));
+
+        let pool_c = UniswapV2Pool {
+            fee: 200_000,
+            ..pool_b.clone()
+        };
+        assert!(matches!(
+            optimal_arb_amount(&pool_c, &pool_a, token_in),
+            Err(ArithmeticError::InvalidFee(20_000))
+        ));
+
+        Ok(())
+    }
+}
@@ -11,35 +11,35 @@ pub enum AMMError<M>
 where
     M: Middleware,
 {
-    #[error("Middleware error")]
-    MiddlewareError(<M as Middleware>::Error),
-    #[error("Provider error")]
+    #[error("Middleware error: {0}")]
+    MiddlewareError(#[source] <M as Middleware>::Error),
+    #[error("Provider error: {0}")]
     ProviderError(#[from] ProviderError),
-    #[error("Contract error")]
+    #[error("Contract error: {0}")]
     ContractError(#[from] ContractError<M>),
-    #[error("ABI Codec error")]
+    #[error("ABI Codec error: {0}")]
     ABICodecError(#[from] AbiError),
-    #[error("Eth ABI error")]
+    #[error("Eth ABI error: {0}")]
     EthABIError(#[from] ethers::abi::Error),
-    #[error("Join error")]
+    #[error("Join error: {0}")]
     JoinError(#[from] JoinError),
-    #[error("Serde json error")]
+    #[error("Serde json error: {0}")]
     SerdeJsonError(#[from] serde_json::error::Error),
-    #[error("IO error")]
+    #[error("IO error: {0}")]
     IOError(#[from] std::io::Error),
     #[error("Error when converting from hex to U256")]
     FromHexError,
-    #[error("Uniswap V3 math error")]
+    #[error("Uniswap V3 math error: {0}")]
     UniswapV3MathError(#[from] UniswapV3MathError),
-    #[error("Pair for token_a/token_b does not exist in provided dexes")]
+    #[error("Pair for {0:?}/{1:?} does not exist in provided dexes")]
     PairDoesNotExistInDexes(H160, H160),
     #[error("Could not initialize new pool from event log")]
     UnrecognizedPoolCreatedEventLog,
-    #[error("Error when syncing pool")]
+    #[error("Error when syncing pool {0:?}")]
     SyncError(H160),
     #[error("Error when getting pool data")]
     PoolDataError,
-    #[error("Arithmetic error")]
+    #[error("Arithmetic error: {0}")]
     ArithmeticError(#[from] ArithmeticError),
     #[error("No initialized ticks during v3 swap simulation")]
     NoInitializedTicks,
@@ -49,21 +49,33 @@ where
     IncongruentAMMs,
     #[error("Invalid ERC4626 fee")]
     InvalidERC4626Fee,
-    #[error("Event log error")]
+    #[error("Pair {0:?} reported an invalid fee of {1}")]
+    InvalidPairFee(H160, u32),
+    #[error("Event log error: {0}")]
     EventLogError(#[from] EventLogError),
     #[error("Block number not found")]
     BlockNumberNotFound,
-    #[error("Swap simulation error")]
+    #[error("Swap simulation error: {0}")]
     SwapSimulationError(#[from] SwapSimulationError),
-    #[error("Invalid data from batch request")]
+    #[error("Invalid data from batch request for {0:?}")]
     BatchRequestError(H160),
-    #[error("Checkpoint error")]
+    #[error("Checkpoint error: {0}")]
     CheckpointError(#[from] CheckpointError),
+    #[error("Router {0:?} quoted {1} but the local swap simulation returned {2}")]
+    RouterParityMismatch(H160, U256, U256),
+    #[error("Reserves of {0:?} exceed uint112")]
+    ReservesOverflow(H160),
+    #[error("No factories configured")]
+    NoFactoriesConfigured,
+    #[error("Log request for blocks {0} to {1} timed out")]
+    Timeout(u64, u64),
+    #[error("Log requests failed for block ranges {0:?}")]
+    BatchRequestFailed(Vec<(u64, u64)>),
 }
 
 #[derive(Error, Debug)]
 pub enum ArithmeticError {
-    #[error("Shadow overflow")]
+    #[error("Shadow overflow: {0}")]
     ShadowOverflow(U256),
     #[error("Rounding Error")]
     RoundingError,
@@ -73,8 +85,14 @@ pub enum ArithmeticError {
     SqrtPriceOverflow,
     #[error("U128 conversion error")]
     U128ConversionError,
-    #[error("Uniswap v3 math error")]
+    #[error("Uniswap v3 math error: {0}")]
     UniswapV3MathError(#[from] UniswapV3MathError),
+    #[error("Token {0:?} is not in the AMM")]
+    InvalidToken(H160),
+    #[error("Arithmetic overflow")]
+    Overflow,
+    #[error("Invalid fee of {0} bps")]
+    InvalidFee(u32),
 }
 
 #[derive(Error, Debug)]
@@ -83,28 +101,69 @@ pub enum EventLogError {
     InvalidEventSignature,
     #[error("Log Block number not found")]
     LogBlockNumberNotFound,
-    #[error("Eth abi error")]
+    #[error("Eth abi error: {0}")]
     EthABIError(#[from] ethers::abi::Error),
-    #[error("ABI error")]
+    #[error("ABI error: {0}")]
     ABIError(#[from] AbiError),
+    #[error("Reserves of {0:?} exceed uint112")]
+    ReservesOverflow(H160),
 }
 
 #[derive(Error, Debug)]
 pub enum SwapSimulationError {
     #[error("Could not get next tick")]
     InvalidTick,
-    #[error("Uniswap v3 math error")]
+    #[error("Uniswap v3 math error: {0}")]
     UniswapV3MathError(#[from] UniswapV3MathError),
     #[error("Liquidity underflow")]
     LiquidityUnderflow,
+    #[error("Insufficient liquidity")]
+    InsufficientLiquidity,
+    #[error("Swap overflows the pool reserves")]
+    ReservesOverflow,
+    #[error("Invalid fee of {0} bps")]
+    InvalidFee(u32),
 }
 
 #[derive(Error, Debug)]
 pub enum CheckpointError {
-    #[error("System time error")]
+    #[error("System time error: {0}")]
     SystemTimeError(#[from] SystemTimeError),
-    #[error("Serde json error")]
+    #[error("Serde json error: {0}")]
     SerdeJsonError(#[from] serde_json::error::Error),
-    #[error("IO error")]
+    #[error("IO error: {0}")]
     IOError(#[from] std::io::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use ethers::{
+        providers::{Http, Provider, ProviderError},
+        types::H160,
+    };
+
+    use super::{AMMError, EventLogError};
+
+    #[test]
+    fn test_error_display_includes_source() {
+        let err: AMMError<Provider<Http>> =
+            EventLogError::ReservesOverflow(H160::from_low_u64_be(1)).into();
+
+        assert_eq!(
+            err.to_string(),
+            "Event log error: Reserves of 0x0000000000000000000000000000000000000001 exceed uint112"
+        );
+        assert!(err
+            .source()
+            .is_some_and(|source| source.is::<EventLogError>()));
+
+        let err: AMMError<Provider<Http>> =
+            AMMError::MiddlewareError(ProviderError::CustomError("execution reverted".into()));
+
+        assert!(err
+            .source()
+            .is_some_and(|source| source.is::<ProviderError>()));
+    }
+}
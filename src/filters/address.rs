@@ -1,6 +1,10 @@
 use crate::amm::{AutomatedMarketMaker, AMM};
-use ethers::types::H160;
-use std::collections::HashSet;
+use ethers::{providers::Middleware, types::H160};
+use futures::{stream, Future, StreamExt};
+use std::{collections::HashSet, sync::Arc};
+
+/// Maximum number of predicate calls in flight in `filter_amms_async`.
+pub const ASYNC_FILTER_CONCURRENCY: usize = 16;
 
 /// Filters out AMMs that contain a blacklisted token.
 pub fn filter_blacklisted_tokens(amms: Vec<AMM>, blacklisted_addresses: Vec<H160>) -> Vec<AMM> {
@@ -66,3 +70,118 @@ pub fn filter_blacklisted_addresses(amms: Vec<AMM>, blacklisted_addresses: Vec<H
 
     filtered_amms
 }
+
+/// Filters out AMMs that contain a token not in the whitelist.
+pub fn filter_by_token_list(amms: Vec<AMM>, whitelist: &HashSet<H160>) -> Vec<AMM> {
+    amms.into_iter()
+        .filter(|amm| amm.tokens().iter().all(|token| whitelist.contains(token)))
+        .collect()
+}
+
+/// Filters out AMMs that contain any token in the blacklist.
+pub fn filter_exclude_token_list(amms: Vec<AMM>, blacklist: &HashSet<H160>) -> Vec<AMM> {
+    amms.into_iter()
+        .filter(|amm| !amm.tokens().iter().any(|token| blacklist.contains(token)))
+        .collect()
+}
+
+/// Filters out AMMs that contain a token failing the async `predicate`.
+///
+/// The predicate is evaluated once per unique token, with at most `ASYNC_FILTER_CONCURRENCY` calls in flight.
+pub async fn filter_amms_async<M, F, Fut>(
+    amms: Vec<AMM>,
+    middleware: Arc<M>,
+    predicate: F,
+) -> Vec<AMM>
+where
+    M: Middleware,
+    F: Fn(H160, Arc<M>) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let tokens: HashSet<H160> = amms.iter().flat_map(|amm| amm.tokens()).collect();
+
+    let rejected_tokens: HashSet<H160> = stream::iter(tokens)
+        .map(|token| {
+            let passed = predicate(token, middleware.clone());
+            async move { (token, passed.await) }
+        })
+        .buffer_unordered(ASYNC_FILTER_CONCURRENCY)
+        .filter_map(|(token, passed)| async move { (!passed).then_some(token) })
+        .collect()
+        .await;
+
+    filter_exclude_token_list(amms, &rejected_tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use ethers::{providers::Provider, types::H160};
+
+    use crate::amm::{uniswap_v2::UniswapV2Pool, AutomatedMarketMaker, AMM};
+
+    use super::{filter_amms_async, filter_by_token_list, filter_exclude_token_list};
+
+    fn amms() -> Vec<AMM> {
+        [(1, 2), (2, 3), (3, 4)]
+            .iter()
+            .enumerate()
+            .map(|(i, (token_a, token_b))| {
+                AMM::UniswapV2Pool(UniswapV2Pool {
+                    address: H160::from_low_u64_be(100 + i as u64),
+                    token_a: H160::from_low_u64_be(*token_a),
+                    token_b: H160::from_low_u64_be(*token_b),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_by_token_list() {
+        let whitelist = HashSet::from([
+            H160::from_low_u64_be(1),
+            H160::from_low_u64_be(2),
+            H160::from_low_u64_be(3),
+        ]);
+
+        let filtered_amms = filter_by_token_list(amms(), &whitelist);
+        let addresses: Vec<H160> = filtered_amms.iter().map(|amm| amm.address()).collect();
+
+        assert_eq!(
+            addresses,
+            vec![H160::from_low_u64_be(100), H160::from_low_u64_be(101)]
+        );
+    }
+
+    #[test]
+    fn test_filter_exclude_token_list() {
+        let blacklist = HashSet::from([H160::from_low_u64_be(1)]);
+
+        let filtered_amms = filter_exclude_token_list(amms(), &blacklist);
+        let addresses: Vec<H160> = filtered_amms.iter().map(|amm| amm.address()).collect();
+
+        assert_eq!(
+            addresses,
+            vec![H160::from_low_u64_be(101), H160::from_low_u64_be(102)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_filter_amms_async() -> eyre::Result<()> {
+        let (middleware, _) = Provider::mocked();
+        let rejected_token = H160::from_low_u64_be(3);
+
+        let filtered_amms =
+            filter_amms_async(amms(), Arc::new(middleware), |token, _| async move {
+                token != rejected_token
+            })
+            .await;
+        let addresses: Vec<H160> = filtered_amms.iter().map(|amm| amm.address()).collect();
+
+        assert_eq!(addresses, vec![H160::from_low_u64_be(100)]);
+
+        Ok(())
+    }
+}
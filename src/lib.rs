@@ -1,4 +1,5 @@
 pub mod amm;
+pub mod arb;
 pub mod discovery;
 pub mod errors;
 pub mod filters;
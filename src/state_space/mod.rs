@@ -28,6 +28,43 @@ use tokio::{
 pub type StateSpace = HashMap<H160, AMM>;
 pub type StateChangeCache = ArrayDeque<StateChange, 150>;
 
+/// A function applied to each log before it is synced into the state space.
+///
+/// Useful to remap addresses when running against a forked chain.
+#[derive(Clone)]
+pub struct LogTransform(Arc<dyn Fn(Log) -> Log + Send + Sync>);
+
+impl LogTransform {
+    pub fn new<F>(transform: F) -> Self
+    where
+        F: Fn(Log) -> Log + Send + Sync + 'static,
+    {
+        Self(Arc::new(transform))
+    }
+
+    pub fn apply(&self, log: Log) -> Log {
+        (self.0)(log)
+    }
+}
+
+impl std::fmt::Debug for LogTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("LogTransform").finish()
+    }
+}
+
+/// What to do when a log can not be synced into an AMM, e.g. because it fails to decode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnLogError {
+    /// Stop handling the logs and return the error.
+    #[default]
+    Abort,
+    /// Skip the log and continue with the next one.
+    Skip,
+    /// Skip the log and keep it for later inspection, see `StateSpaceManager::skipped_logs`.
+    SkipAndCollect,
+}
+
 #[derive(Debug)]
 pub struct StateSpaceManager<M, P>
 where
@@ -44,6 +81,9 @@ where
     pub state_change_cache: Arc<RwLock<StateChangeCache>>,
     pub middleware: Arc<M>,
     pub stream_middleware: Arc<P>,
+    log_transform: Option<LogTransform>,
+    on_log_error: OnLogError,
+    skipped_logs: Arc<RwLock<Vec<Log>>>,
 }
 
 impl<M, P> StateSpaceManager<M, P>
@@ -75,9 +115,32 @@ where
             state_change_cache: Arc::new(RwLock::new(ArrayDeque::new())),
             middleware,
             stream_middleware,
+            log_transform: None,
+            on_log_error: OnLogError::default(),
+            skipped_logs: Arc::new(RwLock::new(vec![])),
         }
     }
 
+    /// Sets what to do when a log can not be synced into an AMM. Defaults to `OnLogError::Abort`.
+    pub fn with_on_log_error(mut self, on_log_error: OnLogError) -> Self {
+        self.on_log_error = on_log_error;
+        self
+    }
+
+    /// Returns the logs skipped so far under `OnLogError::SkipAndCollect`.
+    pub async fn skipped_logs(&self) -> Vec<Log> {
+        self.skipped_logs.read().await.clone()
+    }
+
+    /// Sets a transform applied to each log before it is synced into the state space.
+    pub fn with_log_transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(Log) -> Log + Send + Sync + 'static,
+    {
+        self.log_transform = Some(LogTransform::new(transform));
+        self
+    }
+
     pub async fn filter(&self) -> Filter {
         let mut event_signatures: Vec<H256> = vec![];
         let mut amm_variants = HashSet::new();
@@ -114,6 +177,9 @@ where
         let state = self.state.clone();
         let middleware = self.middleware.clone();
         let filter = self.filter().await;
+        let log_transform = self.log_transform.clone();
+        let on_log_error = self.on_log_error;
+        let skipped_logs = self.skipped_logs.clone();
 
         let (stream_tx, mut stream_rx): (Sender<Block<H256>>, Receiver<Block<H256>>) =
             tokio::sync::mpsc::channel(self.stream_buffer);
@@ -170,6 +236,7 @@ where
                             )
                             .await
                             .map_err(StateSpaceError::MiddlewareError)?;
+                        let logs = transform_logs(logs, log_transform.as_ref());
 
                         if logs.is_empty() {
                             for block_number in from_block..=chain_head_block_number {
@@ -180,13 +247,16 @@ where
                                 .await?;
                             }
                         } else {
-                            let amms_updated = handle_state_changes_from_logs(
-                                state.clone(),
-                                state_change_cache.clone(),
-                                logs,
-                                middleware.clone(),
-                            )
-                            .await?;
+                            let (amms_updated, skipped) =
+                                handle_state_changes_from_logs_with_policy(
+                                    state.clone(),
+                                    state_change_cache.clone(),
+                                    logs,
+                                    on_log_error,
+                                    middleware.clone(),
+                                )
+                                .await?;
+                            skipped_logs.write().await.extend(skipped);
 
                             amms_updated_tx.send(amms_updated).await?;
                         }
@@ -212,6 +282,9 @@ where
         let state = self.state.clone();
         let middleware = self.middleware.clone();
         let filter = self.filter().await;
+        let log_transform = self.log_transform.clone();
+        let on_log_error = self.on_log_error;
+        let skipped_logs = self.skipped_logs.clone();
 
         let (stream_tx, mut stream_rx): (Sender<Block<H256>>, Receiver<Block<H256>>) =
             tokio::sync::mpsc::channel(self.stream_buffer);
@@ -260,6 +333,7 @@ where
                             )
                             .await
                             .map_err(StateSpaceError::MiddlewareError)?;
+                        let logs = transform_logs(logs, log_transform.as_ref());
 
                         if logs.is_empty() {
                             for block_number in from_block..=chain_head_block_number {
@@ -270,13 +344,16 @@ where
                                 .await?;
                             }
                         } else {
-                            let _amms_updated = handle_state_changes_from_logs(
-                                state.clone(),
-                                state_change_cache.clone(),
-                                logs,
-                                middleware.clone(),
-                            )
-                            .await?;
+                            let (_amms_updated, skipped) =
+                                handle_state_changes_from_logs_with_policy(
+                                    state.clone(),
+                                    state_change_cache.clone(),
+                                    logs,
+                                    on_log_error,
+                                    middleware.clone(),
+                                )
+                                .await?;
+                            skipped_logs.write().await.extend(skipped);
                         }
 
                         last_synced_block = chain_head_block_number;
@@ -370,16 +447,39 @@ pub async fn handle_state_changes_from_logs<M: Middleware>(
     state: Arc<RwLock<StateSpace>>,
     state_change_cache: Arc<RwLock<StateChangeCache>>,
     logs: Vec<Log>,
-    _middleware: Arc<M>,
+    middleware: Arc<M>,
 ) -> Result<Vec<H160>, StateChangeError> {
+    let (updated_amms, _) = handle_state_changes_from_logs_with_policy(
+        state,
+        state_change_cache,
+        logs,
+        OnLogError::Abort,
+        middleware,
+    )
+    .await?;
+
+    Ok(updated_amms)
+}
+
+/// Handles state changes from logs, applying `on_log_error` to logs that can not be synced into their AMM.
+///
+/// Returns the addresses of the updated AMMs, and the skipped logs if `on_log_error` is `OnLogError::SkipAndCollect`.
+pub async fn handle_state_changes_from_logs_with_policy<M: Middleware>(
+    state: Arc<RwLock<StateSpace>>,
+    state_change_cache: Arc<RwLock<StateChangeCache>>,
+    logs: Vec<Log>,
+    on_log_error: OnLogError,
+    _middleware: Arc<M>,
+) -> Result<(Vec<H160>, Vec<Log>), StateChangeError> {
     let mut updated_amms_set = HashSet::new();
     let mut updated_amms = vec![];
     let mut state_changes = vec![];
+    let mut skipped_logs = vec![];
 
     let mut last_log_block_number = if let Some(log) = logs.first() {
         get_block_number_from_log(log)?
     } else {
-        return Ok(updated_amms);
+        return Ok((updated_amms, skipped_logs));
     };
 
     for log in logs.into_iter() {
@@ -387,13 +487,27 @@ pub async fn handle_state_changes_from_logs<M: Middleware>(
 
         // check if the log is from an amm in the state space
         if let Some(amm) = state.write().await.get_mut(&log.address) {
-            if !updated_amms_set.contains(&log.address) {
-                updated_amms_set.insert(log.address);
-                updated_amms.push(log.address);
-            }
+            let address = log.address;
+            let amm_before_log = amm.clone();
+            let skipped_log = (on_log_error == OnLogError::SkipAndCollect).then(|| log.clone());
+
+            match amm.sync_from_log(log) {
+                Ok(()) => {
+                    if !updated_amms_set.contains(&address) {
+                        updated_amms_set.insert(address);
+                        updated_amms.push(address);
+                    }
 
-            state_changes.push(amm.clone());
-            amm.sync_from_log(log)?;
+                    state_changes.push(amm_before_log);
+                }
+                Err(err) => match on_log_error {
+                    OnLogError::Abort => return Err(err.into()),
+                    OnLogError::Skip | OnLogError::SkipAndCollect => {
+                        tracing::warn!(?address, ?err, "skipping log that could not be synced");
+                        skipped_logs.extend(skipped_log);
+                    }
+                },
+            }
         }
 
         //Commit state changes if the block has changed since last log
@@ -431,7 +545,18 @@ pub async fn handle_state_changes_from_logs<M: Middleware>(
         .await?;
     };
 
-    Ok(updated_amms)
+    Ok((updated_amms, skipped_logs))
+}
+
+/// Applies the log transform to each log, if one is set.
+pub fn transform_logs(logs: Vec<Log>, log_transform: Option<&LogTransform>) -> Vec<Log> {
+    match log_transform {
+        Some(log_transform) => logs
+            .into_iter()
+            .map(|log| log_transform.apply(log))
+            .collect(),
+        None => logs,
+    }
 }
 
 pub fn get_block_number_from_log(log: &Log) -> Result<u64, EventLogError> {
@@ -446,16 +571,22 @@ pub fn get_block_number_from_log(log: &Log) -> Result<u64, EventLogError> {
 mod tests {
     use std::{default, sync::Arc};
 
-    use crate::amm::{uniswap_v2::UniswapV2Pool, AMM};
+    use crate::amm::{
+        uniswap_v2::{UniswapV2Pool, SYNC_EVENT_SIGNATURE},
+        AMM,
+    };
     use ethers::{
+        abi::Token,
         providers::{Http, Middleware, Provider, Ws},
-        types::H160,
+        types::{Log, H160, U256, U64},
     };
     use tokio::sync::RwLock;
 
     use super::StateSpaceManager;
     use crate::state_space::{
-        add_state_change_to_cache, unwind_state_changes, StateChange, StateChangeCache,
+        add_state_change_to_cache, error::StateChangeError, handle_state_changes_from_logs,
+        handle_state_changes_from_logs_with_policy, initialize_state_space, transform_logs,
+        unwind_state_changes, LogTransform, OnLogError, StateChange, StateChangeCache,
     };
 
     #[tokio::test]
@@ -556,4 +687,129 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_log_transform() -> eyre::Result<()> {
+        let forked_address = H160::from_low_u64_be(1);
+        let pool_address = H160::from_low_u64_be(2);
+
+        let state = Arc::new(RwLock::new(initialize_state_space(vec![
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: pool_address,
+                ..default::Default::default()
+            }),
+        ])));
+        let state_change_cache = Arc::new(RwLock::new(StateChangeCache::new()));
+        let (middleware, _) = Provider::mocked();
+
+        let sync_log = Log {
+            address: forked_address,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: ethers::abi::encode(&[
+                Token::Uint(U256::from(100)),
+                Token::Uint(U256::from(200)),
+            ])
+            .into(),
+            block_number: Some(U64::from(1)),
+            ..Default::default()
+        };
+
+        let log_transform = LogTransform::new(move |mut log: Log| {
+            if log.address == forked_address {
+                log.address = pool_address;
+            }
+            log
+        });
+
+        let logs = transform_logs(vec![sync_log], Some(&log_transform));
+
+        let updated_amms = handle_state_changes_from_logs(
+            state.clone(),
+            state_change_cache,
+            logs,
+            Arc::new(middleware),
+        )
+        .await?;
+
+        assert_eq!(updated_amms, vec![pool_address]);
+
+        if let Some(AMM::UniswapV2Pool(pool)) = state.read().await.get(&pool_address) {
+            assert_eq!(pool.reserve_0, 100);
+            assert_eq!(pool.reserve_1, 200);
+        } else {
+            panic!("Pool not found in state space")
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_on_log_error() -> eyre::Result<()> {
+        let pool_address = H160::from_low_u64_be(2);
+        let sync_log = |block_number: u64, data: Vec<u8>| Log {
+            address: pool_address,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: data.into(),
+            block_number: Some(U64::from(block_number)),
+            ..Default::default()
+        };
+
+        let logs = vec![
+            sync_log(
+                1,
+                ethers::abi::encode(&[Token::Uint(U256::from(100)), Token::Uint(U256::from(200))]),
+            ),
+            //Sync log without data, fails to decode
+            sync_log(1, vec![]),
+            sync_log(
+                2,
+                ethers::abi::encode(&[Token::Uint(U256::from(300)), Token::Uint(U256::from(400))]),
+            ),
+        ];
+
+        let handle_logs = |on_log_error: OnLogError| {
+            let logs = logs.clone();
+            async move {
+                let state = Arc::new(RwLock::new(initialize_state_space(vec![
+                    AMM::UniswapV2Pool(UniswapV2Pool {
+                        address: pool_address,
+                        ..default::Default::default()
+                    }),
+                ])));
+                let (middleware, _) = Provider::mocked();
+
+                let result = handle_state_changes_from_logs_with_policy(
+                    state.clone(),
+                    Arc::new(RwLock::new(StateChangeCache::new())),
+                    logs,
+                    on_log_error,
+                    Arc::new(middleware),
+                )
+                .await;
+
+                let reserves = match state.read().await.get(&pool_address) {
+                    Some(AMM::UniswapV2Pool(pool)) => (pool.reserve_0, pool.reserve_1),
+                    _ => panic!("Pool not found in state space"),
+                };
+
+                (result, reserves)
+            }
+        };
+
+        let (result, _) = handle_logs(OnLogError::Abort).await;
+        assert!(matches!(result, Err(StateChangeError::EventLogError(_))));
+
+        let (result, reserves) = handle_logs(OnLogError::Skip).await;
+        let (updated_amms, skipped_logs) = result?;
+        assert_eq!(updated_amms, vec![pool_address]);
+        assert!(skipped_logs.is_empty());
+        assert_eq!(reserves, (300, 400));
+
+        let (result, reserves) = handle_logs(OnLogError::SkipAndCollect).await;
+        let (_, skipped_logs) = result?;
+        assert_eq!(skipped_logs, vec![logs[1].clone()]);
+        assert_eq!(reserves, (300, 400));
+
+        Ok(())
+    }
 }
@@ -1,35 +1,90 @@
 use std::{
-    fs::read_to_string,
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{read_to_string, File},
+    future::Future,
+    io::BufReader,
     panic::resume_unwind,
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use ethers::{providers::Middleware, types::H160};
+use ethers::{
+    providers::Middleware,
+    types::{H160, U256},
+};
 
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
 
-use tokio::task::JoinHandle;
+use tokio::{
+    sync::{broadcast, Semaphore},
+    task::JoinHandle,
+};
 
 use crate::{
     amm::{
+        best_execution, dedup_amms,
         factory::{AutomatedMarketMakerFactory, Factory},
         uniswap_v2::factory::UniswapV2Factory,
         uniswap_v3::factory::UniswapV3Factory,
-        AMM,
+        AutomatedMarketMaker, AMM,
     },
     errors::{AMMError, CheckpointError},
     filters,
 };
 
-use super::amms_are_congruent;
+use super::{amms_are_congruent, serde_with::deserialize_u64_or_default};
+
+/// Default buffer size of the channel returned by `Checkpoint::subscribe_new_amms`.
+pub const DEFAULT_NEW_AMMS_BUFFER: usize = 1000;
+/// Maximum number of factories synced concurrently by `Checkpoint::new_from_factories_parallel`.
+pub const MAX_CONCURRENT_FACTORY_TASKS: usize = 8;
+/// Upper bound of the backoff delay used by `Checkpoint::from_rpc_with_retry`.
+pub const MAX_RETRY_DELAY_MS: u64 = 60_000;
+
+/// Retry policy used by `Checkpoint::from_rpc_with_retry` when the RPC rate limits requests.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+    // Delay before the first retry, doubled after each retry up to `MAX_RETRY_DELAY_MS`
+    pub initial_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_retries: 5,
+            initial_delay_ms: 1000,
+        }
+    }
+}
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Checkpoint {
     pub timestamp: usize,
+    //Older checkpoints may store the block number as null, a hex string or omit it, 0 means unknown
+    #[serde(default, deserialize_with = "deserialize_u64_or_default")]
     pub block_number: u64,
     pub factories: Vec<Factory>,
     pub amms: Vec<AMM>,
+    #[serde(skip)]
+    new_amms_tx: Option<broadcast::Sender<AMM>>,
+}
+
+//A clone starts without subscribers, so AMMs added to it are not sent to the subscribers of the original
+impl Clone for Checkpoint {
+    fn clone(&self) -> Self {
+        Checkpoint {
+            timestamp: self.timestamp,
+            block_number: self.block_number,
+            factories: self.factories.clone(),
+            amms: self.amms.clone(),
+            new_amms_tx: None,
+        }
+    }
 }
 
 impl Checkpoint {
@@ -44,10 +99,886 @@ impl Checkpoint {
             block_number,
             factories,
             amms,
+            new_amms_tx: None,
+        }
+    }
+
+    /// Creates a checkpoint from AMMs discovered externally, without any factories.
+    ///
+    /// AMMs sharing an address are deduplicated with `dedup_amms`, which also reorders the tokens of Uniswap V2 pools so that
+    /// `token_a` is the lower address. The block number is left at 0 as the AMMs were not synced at a known block.
+    pub fn from_amms(amms: Vec<AMM>) -> Checkpoint {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64() as usize)
+            .unwrap_or_default();
+
+        Checkpoint::new(timestamp, 0, vec![], dedup_amms(amms))
+    }
+
+    /// Loads the checkpoint at `path`, keeping only the AMMs whose address is in `addresses`.
+    ///
+    /// The file is streamed and each AMM is dropped as soon as it is deserialized, so the full list of AMMs is never held in memory.
+    pub fn new_from_file_filtered(
+        path: &str,
+        addresses: &HashSet<H160>,
+    ) -> Result<Checkpoint, CheckpointError> {
+        let mut deserializer =
+            serde_json::Deserializer::from_reader(BufReader::new(File::open(path)?));
+        let checkpoint = FilteredCheckpointSeed { addresses }.deserialize(&mut deserializer)?;
+        deserializer.end()?;
+
+        Ok(checkpoint)
+    }
+
+    /// Syncs all AMMs from the supplied factories into a new checkpoint.
+    ///
+    /// Each RPC phase, i.e. getting the block number and discovering and populating the AMMs of each factory, is retried
+    /// on its own with exponential backoff according to the `retry_config` if the RPC rate limits a request, so a rate
+    /// limit does not restart the phases already completed. Any other error is returned immediately. Factories are synced
+    /// one after another to avoid adding load on a rate limited RPC.
+    pub async fn from_rpc_with_retry<M: 'static + Middleware>(
+        factories: Vec<Factory>,
+        step: u64,
+        middleware: Arc<M>,
+        retry_config: RetryConfig,
+    ) -> Result<Checkpoint, AMMError<M>> {
+        if factories.is_empty() {
+            return Err(AMMError::NoFactoriesConfigured);
+        }
+
+        let current_block = with_rate_limit_retry(retry_config, || async {
+            middleware
+                .get_block_number()
+                .await
+                .map_err(AMMError::MiddlewareError)
+        })
+        .await?
+        .as_u64();
+
+        let mut aggregated_amms = vec![];
+
+        for factory in factories.iter() {
+            let amms = with_rate_limit_retry(retry_config, || {
+                factory.get_all_amms(Some(current_block), middleware.clone(), step)
+            })
+            .await?;
+
+            if amms.is_empty() {
+                continue;
+            }
+
+            let amms = with_rate_limit_retry(retry_config, || {
+                let mut amms = amms.clone();
+                let middleware = middleware.clone();
+
+                async move {
+                    super::populate_amms(&mut amms, current_block, middleware).await?;
+                    Ok(amms)
+                }
+            })
+            .await?;
+
+            let mut amms = filters::filter_empty_amms(amms);
+
+            //Pools start with the factory fee, forks with per pair fees report them via `fee()` on the pair
+            if let Factory::UniswapV2Factory(factory) = factory {
+                amms = with_rate_limit_retry(retry_config, || {
+                    let mut amms = amms.clone();
+                    let middleware = middleware.clone();
+
+                    async move {
+                        factory
+                            .populate_pair_fees(&mut amms, Some(current_block), middleware)
+                            .await?;
+                        Ok(amms)
+                    }
+                })
+                .await?;
+            }
+
+            aggregated_amms.extend(amms);
+        }
+
+        Ok(Checkpoint::new(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(CheckpointError::from)?
+                .as_secs_f64() as usize,
+            current_block,
+            factories,
+            aggregated_amms,
+        ))
+    }
+
+    /// Syncs all AMMs from the supplied factories into a new checkpoint, spawning a task per factory.
+    ///
+    /// Each factory is scanned from its own creation block, with at most `MAX_CONCURRENT_FACTORY_TASKS` factories synced concurrently.
+    pub async fn new_from_factories_parallel<M: 'static + Middleware>(
+        factories: Vec<Factory>,
+        step: u64,
+        middleware: Arc<M>,
+    ) -> Result<Checkpoint, AMMError<M>> {
+        if factories.is_empty() {
+            return Err(AMMError::NoFactoriesConfigured);
+        }
+
+        let current_block = middleware
+            .get_block_number()
+            .await
+            .map_err(AMMError::MiddlewareError)?
+            .as_u64();
+
+        let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_FACTORY_TASKS));
+        let mut handles = vec![];
+
+        for factory in factories.clone() {
+            let middleware = middleware.clone();
+            let semaphore = semaphore.clone();
+
+            handles.push(tokio::spawn(async move {
+                //The semaphore is never closed
+                let _permit = semaphore.acquire_owned().await.expect("semaphore closed");
+
+                let mut amms = factory
+                    .get_all_pools_from_logs(
+                        factory.creation_block(),
+                        current_block,
+                        step,
+                        middleware.clone(),
+                    )
+                    .await?;
+
+                factory
+                    .populate_amm_data(&mut amms, Some(current_block), middleware)
+                    .await?;
+
+                Ok::<_, AMMError<M>>(filters::filter_empty_amms(amms))
+            }));
+        }
+
+        let mut checkpoint = Checkpoint::new(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(CheckpointError::from)?
+                .as_secs_f64() as usize,
+            current_block,
+            factories,
+            vec![],
+        );
+
+        for handle in handles {
+            match handle.await {
+                Ok(sync_result) => checkpoint.add_amms(sync_result?),
+                Err(err) => {
+                    if err.is_panic() {
+                        // Resume the panic on the main task
+                        resume_unwind(err.into_panic());
+                    }
+                }
+            }
+        }
+
+        Ok(checkpoint)
+    }
+
+    /// Returns the ids of the chains the checkpoint factories are deployed on, sorted and deduplicated.
+    ///
+    /// Factories with an unknown chain are not included.
+    pub fn chain_ids(&self) -> Vec<u64> {
+        let mut chain_ids: Vec<u64> = self
+            .factories
+            .iter()
+            .filter_map(|factory| factory.chain_id())
+            .collect();
+
+        chain_ids.sort_unstable();
+        chain_ids.dedup();
+        chain_ids
+    }
+
+    /// Returns a checkpoint with only the factories and AMMs deployed on the chain with `chain_id`.
+    ///
+    /// AMMs are matched through the factory that created them, so AMMs without a known factory are not included.
+    pub fn for_chain(&self, chain_id: u64) -> Checkpoint {
+        let factories: Vec<Factory> = self
+            .factories
+            .iter()
+            .filter(|factory| factory.chain_id() == Some(chain_id))
+            .cloned()
+            .collect();
+
+        let factory_addresses: HashSet<H160> =
+            factories.iter().map(|factory| factory.address()).collect();
+
+        let amms = self
+            .amms
+            .iter()
+            .filter(|amm| match amm {
+                AMM::UniswapV2Pool(pool) => factory_addresses.contains(&pool.factory),
+                _ => false,
+            })
+            .cloned()
+            .collect();
+
+        Checkpoint::new(self.timestamp, self.block_number, factories, amms)
+    }
+
+    /// Returns a receiver that is sent each AMM added to the checkpoint via `add_amms` or `find_new_amms`.
+    pub fn subscribe_new_amms(&mut self) -> broadcast::Receiver<AMM> {
+        self.subscribe_new_amms_with_buffer(DEFAULT_NEW_AMMS_BUFFER)
+    }
+
+    /// Returns a receiver that is sent each AMM added to the checkpoint via `add_amms` or `find_new_amms`.
+    ///
+    /// The `buffer` is only used if there is no existing subscription, otherwise the existing channel is reused.
+    pub fn subscribe_new_amms_with_buffer(&mut self, buffer: usize) -> broadcast::Receiver<AMM> {
+        match &self.new_amms_tx {
+            Some(new_amms_tx) => new_amms_tx.subscribe(),
+            None => {
+                let (new_amms_tx, new_amms_rx) = broadcast::channel(buffer);
+                self.new_amms_tx = Some(new_amms_tx);
+                new_amms_rx
+            }
+        }
+    }
+
+    /// Adds the AMMs to the checkpoint, notifying any subscribers of each new AMM.
+    pub fn add_amms(&mut self, amms: Vec<AMM>) {
+        for amm in amms {
+            if let Some(new_amms_tx) = &self.new_amms_tx {
+                //An error is only returned when there are no active receivers
+                let _ = new_amms_tx.send(amm.clone());
+            }
+
+            self.amms.push(amm);
+        }
+    }
+
+    /// Gets all AMMs created by the checkpoint factories since the checkpoint block and adds them to the checkpoint.
+    ///
+    /// Returns the number of new AMMs found.
+    pub async fn find_new_amms<M: 'static + Middleware>(
+        &mut self,
+        step: u64,
+        middleware: Arc<M>,
+    ) -> Result<usize, AMMError<M>> {
+        //Without factories, the checkpoint block would default to a scan from genesis
+        if self.factories.is_empty() {
+            return Err(AMMError::NoFactoriesConfigured);
+        }
+
+        let current_block = middleware
+            .get_block_number()
+            .await
+            .map_err(AMMError::MiddlewareError)?
+            .as_u64();
+
+        let handles = get_new_amms_from_range(
+            self.factories.clone(),
+            self.block_number,
+            current_block,
+            step,
+            middleware,
+        )
+        .await;
+
+        let mut new_amms = vec![];
+        for handle in handles {
+            match handle.await {
+                Ok(sync_result) => new_amms.extend(sync_result?),
+                Err(err) => {
+                    if err.is_panic() {
+                        // Resume the panic on the main task
+                        resume_unwind(err.into_panic());
+                    }
+                }
+            }
+        }
+
+        let new_amms_count = new_amms.len();
+        self.add_amms(new_amms);
+        self.block_number = current_block;
+
+        Ok(new_amms_count)
+    }
+
+    /// Returns the addresses of AMMs created by the checkpoint factories between `from_block` and `to_block` that are not in the checkpoint.
+    ///
+    /// The checkpoint is not modified.
+    pub async fn find_missing_pools<M: 'static + Middleware>(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        step: u64,
+        middleware: Arc<M>,
+    ) -> Result<Vec<H160>, AMMError<M>> {
+        if self.factories.is_empty() {
+            return Err(AMMError::NoFactoriesConfigured);
+        }
+
+        let tracked_amms: HashSet<H160> = self.amms.iter().map(|amm| amm.address()).collect();
+
+        let mut missing_pools = vec![];
+        for factory in self.factories.iter() {
+            let from_block = from_block.max(factory.creation_block());
+
+            for amm in factory
+                .get_all_pools_from_logs(from_block, to_block, step, middleware.clone())
+                .await?
+            {
+                if !tracked_amms.contains(&amm.address()) {
+                    missing_pools.push(amm.address());
+                }
+            }
+        }
+
+        Ok(missing_pools)
+    }
+
+    /// Adds the AMMs created by the checkpoint factories since the checkpoint block and populates the token metadata of
+    /// every AMM missing it, leaving the reserves of all AMMs untouched.
+    ///
+    /// New AMMs are added without reserves. The checkpoint block is not updated, so a later `find_new_amms` still populates their reserves.
+    pub async fn sync_metadata_only<M: 'static + Middleware>(
+        &mut self,
+        step: u64,
+        middleware: Arc<M>,
+    ) -> Result<MetadataSyncReport, AMMError<M>> {
+        if self.factories.is_empty() {
+            return Err(AMMError::NoFactoriesConfigured);
+        }
+
+        let current_block = middleware
+            .get_block_number()
+            .await
+            .map_err(AMMError::MiddlewareError)?
+            .as_u64();
+
+        let tracked_amms: HashSet<H160> = self.amms.iter().map(|amm| amm.address()).collect();
+        let mut new_amms = vec![];
+
+        for factory in self.factories.iter() {
+            let from_block = self.block_number.max(factory.creation_block());
+
+            for amm in factory
+                .get_all_pools_from_logs(from_block, current_block, step, middleware.clone())
+                .await?
+            {
+                if !tracked_amms.contains(&amm.address()) {
+                    new_amms.push(amm);
+                }
+            }
+        }
+
+        let mut report = MetadataSyncReport {
+            new_amms: new_amms.len(),
+            ..Default::default()
+        };
+        self.add_amms(new_amms);
+
+        for amm in self.amms.iter_mut() {
+            if !amm_missing_metadata(amm) || !amm.supports_populate_data() {
+                continue;
+            }
+
+            //Populating the data also updates the reserves, which are rolled back to their current state
+            let state = amm.state_snapshot();
+            amm.populate_data(Some(current_block), middleware.clone())
+                .await?;
+            amm.restore_state(state);
+
+            if !amm_missing_metadata(amm) {
+                report.amms_with_metadata_filled += 1;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Returns every AMM holding `token`.
+    pub fn pools_with_token(&self, token: H160) -> Vec<&AMM> {
+        self.amms
+            .iter()
+            .filter(|amm| amm.tokens().contains(&token))
+            .collect()
+    }
+
+    /// Returns the price of `token` in terms of `quote`, composed along the path of at most `max_hops` AMMs yielding the highest price.
+    ///
+    /// Returns `None` if there is no path from `token` to `quote`.
+    pub fn price_in(&self, token: H160, quote: H160, max_hops: usize) -> Option<f64> {
+        let mut amms_by_token: HashMap<H160, Vec<&AMM>> = HashMap::new();
+        for amm in self.amms.iter() {
+            for amm_token in amm.tokens() {
+                amms_by_token.entry(amm_token).or_default().push(amm);
+            }
+        }
+
+        let mut visited = HashSet::from([token]);
+        best_composed_price(token, quote, max_hops, 1.0, &amms_by_token, &mut visited)
+    }
+
+    /// Returns the fraction of the total liquidity, valued in `quote_token`, held by `pool` among all AMMs trading the same tokens.
+    ///
+    /// Returns `None` if `pool` is not in the checkpoint, does not hold `quote_token` or the pair has no liquidity.
+    pub fn pool_liquidity_share(&self, pool: H160, quote_token: H160) -> Option<f64> {
+        let sorted_tokens = |amm: &AMM| {
+            let mut tokens = amm.tokens();
+            tokens.sort();
+            tokens
+        };
+
+        let amm = self.amms.iter().find(|amm| amm.address() == pool)?;
+        let pool_liquidity = amm.total_value_locked(quote_token).ok()?;
+        let pair = sorted_tokens(amm);
+
+        let total_liquidity = self
+            .amms
+            .iter()
+            .filter(|amm| sorted_tokens(amm) == pair)
+            .filter_map(|amm| amm.total_value_locked(quote_token).ok())
+            .sum::<f64>();
+
+        if total_liquidity > 0.0 {
+            Some(pool_liquidity / total_liquidity)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the largest price deviation between AMMs trading the same pair, in basis points, for every pair traded on more than one AMM.
+    ///
+    /// Prices are quoted in `base_token` for pairs containing it, and in the lower token address otherwise. The deviation is
+    /// `(max_price - min_price) / min_price`. Entries are `(token_0, token_1, max_deviation_bps)` with the tokens sorted, in descending order of deviation.
+    pub fn price_dislocations(&self, base_token: H160) -> Vec<(H160, H160, f64)> {
+        let mut prices_by_pair: HashMap<(H160, H160), Vec<f64>> = HashMap::new();
+
+        for amm in self.amms.iter() {
+            let mut tokens = amm.tokens();
+            tokens.sort();
+
+            let [token_0, token_1] = tokens[..] else {
+                continue;
+            };
+
+            let priced_token = if token_1 == base_token {
+                token_1
+            } else {
+                token_0
+            };
+
+            if let Ok(price) = amm.calculate_price(priced_token) {
+                if price.is_finite() && price > 0.0 {
+                    prices_by_pair
+                        .entry((token_0, token_1))
+                        .or_default()
+                        .push(price);
+                }
+            }
+        }
+
+        let mut dislocations = prices_by_pair
+            .into_iter()
+            .filter(|(_, prices)| prices.len() > 1)
+            .map(|((token_0, token_1), prices)| {
+                let min_price = prices.iter().copied().fold(f64::INFINITY, f64::min);
+                let max_price = prices.iter().copied().fold(0.0, f64::max);
+
+                (
+                    token_0,
+                    token_1,
+                    (max_price - min_price) / min_price * 10000.0,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        dislocations.sort_by(|a, b| b.2.total_cmp(&a.2));
+        dislocations
+    }
+
+    /// Removes all AMMs containing a token that is not in the whitelist.
+    pub fn apply_token_whitelist(&mut self, whitelist: &HashSet<H160>) {
+        self.amms =
+            filters::address::filter_by_token_list(std::mem::take(&mut self.amms), whitelist);
+    }
+
+    /// Checks the checkpoint for inconsistencies, e.g. after loading it from an untrusted file.
+    ///
+    /// Returns every issue found rather than stopping at the first one.
+    pub fn validate(&self) -> Result<(), Vec<CheckpointIssue>> {
+        let mut issues = vec![];
+        let mut seen_amms = HashSet::new();
+        let block_number_known = self.block_number != 0;
+
+        if !block_number_known {
+            issues.push(CheckpointIssue::UnknownBlockNumber);
+        }
+
+        for factory in self.factories.iter() {
+            if block_number_known && factory.creation_block() > self.block_number {
+                issues.push(CheckpointIssue::FactoryAheadOfCheckpoint(
+                    factory.address(),
+                    factory.creation_block(),
+                ));
+            }
+        }
+
+        for amm in self.amms.iter() {
+            let address = amm.address();
+
+            if address.is_zero() {
+                issues.push(CheckpointIssue::ZeroAddressAmm);
+                continue;
+            }
+
+            if !seen_amms.insert(address) {
+                issues.push(CheckpointIssue::DuplicateAmm(address));
+            }
+
+            if amm.tokens().iter().any(|token| token.is_zero()) {
+                issues.push(CheckpointIssue::ZeroAddressToken(address));
+            }
+
+            if let AMM::UniswapV2Pool(pool) = amm {
+                if block_number_known && pool.creation_block > self.block_number {
+                    issues.push(CheckpointIssue::AmmAheadOfCheckpoint(
+                        address,
+                        pool.creation_block,
+                    ));
+                }
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+
+    /// Returns an estimate of the memory used by the checkpoint in bytes, including its factories and AMMs.
+    pub fn total_memory_bytes(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.factories.capacity() * std::mem::size_of::<Factory>()
+            + (self.amms.capacity() - self.amms.len()) * std::mem::size_of::<AMM>()
+            + self
+                .amms
+                .iter()
+                .map(|amm| amm.memory_size_bytes())
+                .sum::<usize>()
+    }
+
+    /// Returns a summary of the data quality of the AMMs in the checkpoint.
+    pub fn health_report(&self) -> HealthReport {
+        let mut report = HealthReport {
+            total_amms: self.amms.len(),
+            block_number: self.block_number,
+            ..Default::default()
+        };
+
+        for amm in self.amms.iter() {
+            let (populated, empty, missing_decimals) = match amm {
+                AMM::UniswapV2Pool(pool) => (
+                    pool.data_is_populated(),
+                    pool.token_a.is_zero() || pool.token_b.is_zero(),
+                    pool.token_a_decimals == 0 || pool.token_b_decimals == 0,
+                ),
+                AMM::UniswapV3Pool(pool) => (
+                    pool.data_is_populated(),
+                    pool.token_a.is_zero() || pool.token_b.is_zero(),
+                    pool.token_a_decimals == 0 || pool.token_b_decimals == 0,
+                ),
+                AMM::ERC4626Vault(vault) => (
+                    vault.data_is_populated(),
+                    vault.vault_token.is_zero() || vault.asset_token.is_zero(),
+                    vault.vault_token_decimals == 0 || vault.asset_token_decimals == 0,
+                ),
+            };
+
+            if populated {
+                report.populated_amms += 1;
+            }
+
+            if empty {
+                report.empty_amms += 1;
+            }
+
+            if missing_decimals {
+                report.amms_missing_decimals += 1;
+            }
+        }
+
+        report
+    }
+
+    /// Returns the best execution of the swap among the checkpoint AMMs, see `best_execution`.
+    pub fn best_pool_for_swap(
+        &self,
+        token_in: H160,
+        token_out: H160,
+        amount_in: U256,
+    ) -> Option<(H160, U256)> {
+        best_execution(&self.amms, token_in, token_out, amount_in)
+    }
+}
+
+//Depth first search for the highest price of `token` in `quote`, where `price` is the price composed along the path so far
+fn best_composed_price(
+    token: H160,
+    quote: H160,
+    hops_left: usize,
+    price: f64,
+    amms_by_token: &HashMap<H160, Vec<&AMM>>,
+    visited: &mut HashSet<H160>,
+) -> Option<f64> {
+    if token == quote {
+        return Some(price);
+    }
+
+    if hops_left == 0 {
+        return None;
+    }
+
+    let mut best_price: Option<f64> = None;
+
+    for amm in amms_by_token.get(&token).into_iter().flatten() {
+        let next_token = amm.get_token_out(token);
+        if visited.contains(&next_token) {
+            continue;
+        }
+
+        let hop_price = match amm.calculate_price(token) {
+            Ok(hop_price) if hop_price.is_finite() && hop_price > 0.0 => hop_price,
+            _ => continue,
+        };
+
+        visited.insert(next_token);
+        let path_price = best_composed_price(
+            next_token,
+            quote,
+            hops_left - 1,
+            price * hop_price,
+            amms_by_token,
+            visited,
+        );
+        visited.remove(&next_token);
+
+        if let Some(path_price) = path_price {
+            best_price = Some(best_price.unwrap_or(path_price).max(path_price));
+        }
+    }
+
+    best_price
+}
+
+/// Returns whether the error was caused by the RPC rate limiting requests.
+pub fn is_rate_limit_error<M: Middleware>(err: &AMMError<M>) -> bool {
+    let message = match err {
+        AMMError::MiddlewareError(err) => err.to_string(),
+        AMMError::ProviderError(err) => err.to_string(),
+        AMMError::ContractError(err) => err.to_string(),
+        _ => return false,
+    };
+
+    is_rate_limit_message(&message)
+}
+
+/// Returns whether the error message indicates that the RPC is rate limiting requests.
+///
+/// Only the JSON-RPC error code and HTTP status 429 are matched, not any 429 in the message, which could be part of an
+/// address, calldata or a block number.
+pub(crate) fn is_rate_limit_message(message: &str) -> bool {
+    let message = message.to_lowercase();
+
+    message.contains("code: 429")
+        || message.contains("status code 429")
+        || message.contains("rate limit")
+        || message.contains("too many requests")
+}
+
+//Runs `f` until it succeeds, retrying rate limit errors with exponential backoff according to the `retry_config`
+async fn with_rate_limit_retry<M, T, F, Fut>(
+    retry_config: RetryConfig,
+    mut f: F,
+) -> Result<T, AMMError<M>>
+where
+    M: Middleware,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, AMMError<M>>>,
+{
+    let mut retries = 0;
+    let mut delay_ms = retry_config.initial_delay_ms;
+
+    loop {
+        match f().await {
+            Err(err) if retries < retry_config.max_retries && is_rate_limit_error(&err) => {
+                tracing::warn!(retries, delay_ms, "rate limited, retrying request");
+
+                tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+
+                retries += 1;
+                delay_ms = (delay_ms * 2).min(MAX_RETRY_DELAY_MS);
+            }
+
+            result => return result,
+        }
+    }
+}
+
+/// An inconsistency reported by `Checkpoint::validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckpointIssue {
+    /// An AMM has the zero address.
+    ZeroAddressAmm,
+    /// The AMM appears more than once in the checkpoint.
+    DuplicateAmm(H160),
+    /// One of the AMM tokens is the zero address.
+    ZeroAddressToken(H160),
+    /// The AMM was created after the checkpoint block.
+    AmmAheadOfCheckpoint(H160, u64),
+    /// The factory was created after the checkpoint block.
+    FactoryAheadOfCheckpoint(H160, u64),
+    /// The checkpoint block number is missing or 0, so creation blocks cannot be checked.
+    UnknownBlockNumber,
+}
+
+//Deserializes a checkpoint, keeping only the AMMs in `addresses`
+struct FilteredCheckpointSeed<'a> {
+    addresses: &'a HashSet<H160>,
+}
+
+//Deserializes a list of AMMs, keeping only the AMMs in `addresses`
+struct FilteredAmmsSeed<'a> {
+    addresses: &'a HashSet<H160>,
+}
+
+#[derive(Deserialize)]
+struct BlockNumber(#[serde(deserialize_with = "deserialize_u64_or_default")] u64);
+
+impl<'de, 'a> DeserializeSeed<'de> for FilteredCheckpointSeed<'a> {
+    type Value = Checkpoint;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Checkpoint, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for FilteredCheckpointSeed<'a> {
+    type Value = Checkpoint;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a checkpoint")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Checkpoint, A::Error> {
+        let mut timestamp = None;
+        let mut block_number = None;
+        let mut factories = None;
+        let mut amms = None;
+
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "timestamp" => timestamp = Some(map.next_value()?),
+                "block_number" => block_number = Some(map.next_value::<BlockNumber>()?.0),
+                "factories" => factories = Some(map.next_value()?),
+                "amms" => {
+                    amms = Some(map.next_value_seed(FilteredAmmsSeed {
+                        addresses: self.addresses,
+                    })?)
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
         }
+
+        Ok(Checkpoint::new(
+            timestamp.ok_or_else(|| de::Error::missing_field("timestamp"))?,
+            block_number.unwrap_or_default(),
+            factories.ok_or_else(|| de::Error::missing_field("factories"))?,
+            amms.ok_or_else(|| de::Error::missing_field("amms"))?,
+        ))
     }
 }
 
+impl<'de, 'a> DeserializeSeed<'de> for FilteredAmmsSeed<'a> {
+    type Value = Vec<AMM>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Vec<AMM>, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 'a> Visitor<'de> for FilteredAmmsSeed<'a> {
+    type Value = Vec<AMM>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of AMMs")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<AMM>, A::Error> {
+        let mut amms = vec![];
+
+        while let Some(amm) = seq.next_element::<AMM>()? {
+            if self.addresses.contains(&amm.address()) {
+                amms.push(amm);
+            }
+        }
+
+        Ok(amms)
+    }
+}
+
+/// Counts returned by `Checkpoint::sync_metadata_only`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataSyncReport {
+    pub new_amms: usize,
+    // AMMs that were missing token addresses or decimals and are now populated
+    pub amms_with_metadata_filled: usize,
+}
+
+//Returns whether any of the AMM token addresses or decimals are missing
+fn amm_missing_metadata(amm: &AMM) -> bool {
+    match amm {
+        AMM::UniswapV2Pool(pool) => {
+            pool.token_a.is_zero()
+                || pool.token_b.is_zero()
+                || pool.token_a_decimals == 0
+                || pool.token_b_decimals == 0
+        }
+        AMM::UniswapV3Pool(pool) => {
+            pool.token_a.is_zero()
+                || pool.token_b.is_zero()
+                || pool.token_a_decimals == 0
+                || pool.token_b_decimals == 0
+        }
+        AMM::ERC4626Vault(vault) => {
+            vault.asset_token.is_zero()
+                || vault.vault_token_decimals == 0
+                || vault.asset_token_decimals == 0
+        }
+    }
+}
+
+/// Summary of the data quality of the AMMs in a checkpoint.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HealthReport {
+    /// Number of AMMs in the checkpoint.
+    pub total_amms: usize,
+    /// AMMs whose token addresses and reserves are populated.
+    pub populated_amms: usize,
+    /// AMMs missing at least one token address.
+    pub empty_amms: usize,
+    /// AMMs where at least one token reports zero decimals.
+    pub amms_missing_decimals: usize,
+    /// Block number of the checkpoint.
+    ///
+    /// AMMs do not record the block they were last synced at, so the report carries the checkpoint block
+    /// instead of the spread between the oldest and newest synced AMM.
+    pub block_number: u64,
+}
+
 //Get all pairs from last synced block and sync reserve values for each Dex in the `dexes` vec.
 pub async fn sync_amms_from_checkpoint<M: 'static + Middleware>(
     path_to_checkpoint: &str,
@@ -152,6 +1083,9 @@ pub async fn get_new_amms_from_range<M: 'static + Middleware>(
     for factory in factories.into_iter() {
         let middleware = middleware.clone();
 
+        //Factories deployed after the checkpoint do not need to scan the blocks before their creation
+        let from_block = from_block.max(factory.creation_block());
+
         //Spawn a new thread to get all pools and sync data for each dex
         handles.push(tokio::spawn(async move {
             let mut amms = factory
@@ -243,6 +1177,9 @@ pub async fn get_new_pools_from_range<M: 'static + Middleware>(
     for factory in factories {
         let middleware = middleware.clone();
 
+        //Factories deployed after the checkpoint do not need to scan the blocks before their creation
+        let from_block = from_block.max(factory.creation_block());
+
         //Spawn a new thread to get all pools and sync data for each dex
         handles.push(tokio::spawn(async move {
             let mut pools = factory
@@ -286,3 +1223,1008 @@ pub fn deconstruct_checkpoint(checkpoint_path: &str) -> Result<(Vec<AMM>, u64),
     let checkpoint: Checkpoint = serde_json::from_str(read_to_string(checkpoint_path)?.as_str())?;
     Ok((checkpoint.amms, checkpoint.block_number))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, str::FromStr, sync::Arc};
+
+    use ethers::{
+        abi::Token,
+        providers::{JsonRpcError, MockResponse, Provider},
+        types::{Bytes, Log, H160, H256, U256, U64},
+    };
+
+    use crate::{
+        amm::{
+            erc_4626::ERC4626Vault,
+            factory::{AutomatedMarketMakerFactory, Factory},
+            uniswap_v2::{
+                factory::{UniswapV2Factory, PAIR_CREATED_EVENT_SIGNATURE},
+                UniswapV2Pool,
+            },
+            AutomatedMarketMaker, AMM,
+        },
+        errors::AMMError,
+    };
+
+    use super::{
+        construct_checkpoint, is_rate_limit_message, Checkpoint, CheckpointIssue, RetryConfig,
+    };
+
+    #[test]
+    fn test_health_report() {
+        let populated_pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(1),
+            token_a: H160::from_low_u64_be(2),
+            token_a_decimals: 6,
+            token_b: H160::from_low_u64_be(3),
+            token_b_decimals: 18,
+            reserve_0: 1000,
+            reserve_1: 1000,
+            fee: 300,
+            ..Default::default()
+        });
+
+        let unsynced_pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(4),
+            token_a: H160::from_low_u64_be(2),
+            token_b: H160::from_low_u64_be(5),
+            ..Default::default()
+        });
+
+        let empty_pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(6),
+            ..Default::default()
+        });
+
+        let populated_vault = AMM::ERC4626Vault(ERC4626Vault {
+            vault_token: H160::from_low_u64_be(7),
+            vault_token_decimals: 18,
+            asset_token: H160::from_low_u64_be(3),
+            asset_token_decimals: 18,
+            vault_reserve: U256::from(1000),
+            asset_reserve: U256::from(1000),
+            ..Default::default()
+        });
+
+        let checkpoint = Checkpoint::new(
+            0,
+            100,
+            vec![],
+            vec![populated_pool, unsynced_pool, empty_pool, populated_vault],
+        );
+
+        let report = checkpoint.health_report();
+
+        assert_eq!(report.total_amms, 4);
+        assert_eq!(report.populated_amms, 2);
+        assert_eq!(report.empty_amms, 1);
+        assert_eq!(report.amms_missing_decimals, 2);
+        assert_eq!(report.block_number, 100);
+    }
+
+    #[test]
+    fn test_from_amms() {
+        let token_a = H160::from_low_u64_be(10);
+        let token_b = H160::from_low_u64_be(11);
+
+        let checkpoint = Checkpoint::from_amms(
+            [
+                UniswapV2Pool::test_pool(1, token_a, token_b, 100, 0),
+                UniswapV2Pool::test_pool(2, token_a, token_b, 200, 0),
+                UniswapV2Pool::test_pool(1, token_a, token_b, 300, 0),
+            ]
+            .into_iter()
+            .map(AMM::UniswapV2Pool)
+            .collect(),
+        );
+
+        assert!(checkpoint.factories.is_empty());
+        assert_eq!(checkpoint.block_number, 0);
+        assert!(checkpoint.timestamp > 0);
+        assert_eq!(checkpoint.amms.len(), 2);
+        assert!(matches!(
+            &checkpoint.amms[0],
+            AMM::UniswapV2Pool(pool) if pool.address == H160::from_low_u64_be(1) && pool.reserve_0 == 300
+        ));
+    }
+
+    #[test]
+    fn test_pool_liquidity_share() {
+        const ONE: u128 = 1_000_000_000_000_000_000;
+        let weth = H160::from_low_u64_be(10);
+        let usdc = H160::from_low_u64_be(11);
+
+        let checkpoint = Checkpoint::new(
+            0,
+            0,
+            vec![],
+            [
+                UniswapV2Pool::test_pool(1, weth, usdc, 3_000 * ONE, 6_000_000 * ONE),
+                //Same pair with the tokens in the opposite order
+                UniswapV2Pool::test_pool(2, usdc, weth, 2_000_000 * ONE, 1_000 * ONE),
+                //Different pair, not counted
+                UniswapV2Pool::test_pool(
+                    3,
+                    weth,
+                    H160::from_low_u64_be(12),
+                    5_000 * ONE,
+                    5_000 * ONE,
+                ),
+            ]
+            .into_iter()
+            .map(AMM::UniswapV2Pool)
+            .collect(),
+        );
+
+        assert_eq!(
+            checkpoint.pool_liquidity_share(H160::from_low_u64_be(1), weth),
+            Some(0.75)
+        );
+        assert_eq!(
+            checkpoint.pool_liquidity_share(H160::from_low_u64_be(2), usdc),
+            Some(0.25)
+        );
+        assert_eq!(
+            checkpoint.pool_liquidity_share(H160::from_low_u64_be(3), weth),
+            Some(1.0)
+        );
+        assert_eq!(
+            checkpoint.pool_liquidity_share(H160::from_low_u64_be(1), H160::from_low_u64_be(12)),
+            None
+        );
+        assert_eq!(
+            checkpoint.pool_liquidity_share(H160::from_low_u64_be(4), weth),
+            None
+        );
+    }
+
+    #[test]
+    fn test_price_dislocations() {
+        let weth = H160::from_low_u64_be(10);
+        let usdc = H160::from_low_u64_be(11);
+        let dai = H160::from_low_u64_be(12);
+
+        let checkpoint = Checkpoint::new(
+            0,
+            0,
+            vec![],
+            [
+                UniswapV2Pool::test_pool(1, weth, usdc, 1_000, 2_000_000),
+                UniswapV2Pool::test_pool(2, weth, usdc, 1_000, 2_020_000),
+                UniswapV2Pool::test_pool(3, dai, usdc, 1_000_000, 1_000_000),
+                UniswapV2Pool::test_pool(4, usdc, dai, 1_000_000, 1_000_000),
+                //Only traded on a single pool, not reported
+                UniswapV2Pool::test_pool(5, weth, dai, 1_000, 2_000_000),
+            ]
+            .into_iter()
+            .map(AMM::UniswapV2Pool)
+            .collect(),
+        );
+
+        let dislocations = checkpoint.price_dislocations(weth);
+
+        assert_eq!(dislocations.len(), 2);
+        assert_eq!((dislocations[0].0, dislocations[0].1), (weth, usdc));
+        assert!((dislocations[0].2 - 100.0).abs() < 1e-6);
+        assert_eq!((dislocations[1].0, dislocations[1].1), (usdc, dai));
+        assert_eq!(dislocations[1].2, 0.0);
+    }
+
+    #[test]
+    fn test_pools_with_token() {
+        let weth = H160::from_low_u64_be(10);
+        let usdc = H160::from_low_u64_be(11);
+        let dai = H160::from_low_u64_be(12);
+        let checkpoint = Checkpoint::new(
+            0,
+            0,
+            vec![],
+            [
+                UniswapV2Pool::test_pool(1, weth, usdc, 0, 0),
+                UniswapV2Pool::test_pool(2, usdc, dai, 0, 0),
+                UniswapV2Pool::test_pool(3, dai, weth, 0, 0),
+            ]
+            .into_iter()
+            .map(AMM::UniswapV2Pool)
+            .collect(),
+        );
+
+        let addresses = checkpoint
+            .pools_with_token(weth)
+            .iter()
+            .map(|amm| amm.address())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            addresses,
+            vec![H160::from_low_u64_be(1), H160::from_low_u64_be(3)]
+        );
+        assert!(checkpoint
+            .pools_with_token(H160::from_low_u64_be(13))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_total_memory_bytes() {
+        let mut pool = UniswapV2Pool::default();
+        pool.track_reserves(10);
+        let amms = vec![
+            AMM::UniswapV2Pool(pool),
+            AMM::ERC4626Vault(ERC4626Vault::default()),
+        ];
+
+        let amms_size = amms
+            .iter()
+            .map(|amm| amm.memory_size_bytes())
+            .sum::<usize>();
+        let checkpoint = Checkpoint::new(0, 0, vec![], amms);
+
+        assert_eq!(
+            checkpoint.total_memory_bytes(),
+            std::mem::size_of::<Checkpoint>() + amms_size
+        );
+        assert!(amms_size > 2 * std::mem::size_of::<AMM>());
+    }
+
+    #[test]
+    fn test_best_pool_for_swap() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+
+        let shallow_pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(10),
+            token_a,
+            token_a_decimals: 18,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0: 10_000_000_000_000_000_000,
+            reserve_1: 10_000_000_000_000_000_000,
+            fee: 300,
+            ..Default::default()
+        });
+
+        let deep_pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(11),
+            token_a,
+            token_a_decimals: 18,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0: 1_000_000_000_000_000_000_000,
+            reserve_1: 1_000_000_000_000_000_000_000,
+            fee: 300,
+            ..Default::default()
+        });
+
+        let unrelated_pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(12),
+            token_a,
+            token_a_decimals: 18,
+            token_b: H160::from_low_u64_be(3),
+            token_b_decimals: 18,
+            reserve_0: 1_000_000_000_000_000_000_000_000,
+            reserve_1: 1_000_000_000_000_000_000_000_000,
+            fee: 300,
+            ..Default::default()
+        });
+
+        let checkpoint = Checkpoint::new(
+            0,
+            100,
+            vec![],
+            vec![shallow_pool.clone(), deep_pool.clone(), unrelated_pool],
+        );
+
+        let amount_in = U256::from(5_000_000_000_000_000_000_u128);
+        let (best_pool, amount_out) = checkpoint
+            .best_pool_for_swap(token_a, token_b, amount_in)
+            .expect("Could not find a pool for the pair");
+
+        assert_eq!(best_pool, deep_pool.address());
+        assert_eq!(
+            amount_out,
+            deep_pool.simulate_swap(token_a, amount_in).unwrap()
+        );
+        assert!(amount_out > shallow_pool.simulate_swap(token_a, amount_in).unwrap());
+
+        assert!(checkpoint
+            .best_pool_for_swap(token_b, H160::from_low_u64_be(4), amount_in)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_new_amms() -> eyre::Result<()> {
+        let factory_address = H160::from_low_u64_be(100);
+        let mut checkpoint = Checkpoint::new(
+            0,
+            10,
+            vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+                factory_address,
+                0,
+                300,
+            ))],
+            vec![],
+        );
+
+        let mut new_amms_rx = checkpoint.subscribe_new_amms();
+
+        let mut logs = vec![];
+        let mut pool_data = vec![];
+        for i in 1..=5 {
+            let token_0 = H160::from_low_u64_be(i * 10);
+            let token_1 = H160::from_low_u64_be(i * 10 + 1);
+
+            logs.push(Log {
+                address: factory_address,
+                topics: vec![
+                    PAIR_CREATED_EVENT_SIGNATURE,
+                    H256::from(token_0),
+                    H256::from(token_1),
+                ],
+                data: ethers::abi::encode(&[
+                    Token::Address(H160::from_low_u64_be(i)),
+                    Token::Uint(U256::from(i)),
+                ])
+                .into(),
+                block_number: Some(U64::from(15)),
+                ..Default::default()
+            });
+
+            pool_data.push(Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(1000)),
+            ]));
+        }
+
+        //Mocked responses are returned in reverse order, standard pairs do not implement `fee()`
+        let (middleware, mock) = Provider::mocked();
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: None,
+        }));
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(pool_data)])))?;
+        mock.push::<Vec<Log>, _>(logs)?;
+        mock.push::<U64, _>(U64::from(20))?;
+
+        let new_amms_count = checkpoint.find_new_amms(100, Arc::new(middleware)).await?;
+
+        assert_eq!(new_amms_count, 5);
+        assert_eq!(checkpoint.amms.len(), 5);
+        assert_eq!(checkpoint.block_number, 20);
+
+        for i in 1..=5 {
+            let amm = new_amms_rx.try_recv()?;
+            assert_eq!(amm.address(), H160::from_low_u64_be(i));
+            assert!(matches!(amm, AMM::UniswapV2Pool(ref pool) if pool.fee == 300));
+        }
+
+        assert!(new_amms_rx.try_recv().is_err());
+
+        let mut cloned_checkpoint = checkpoint.clone();
+        cloned_checkpoint.add_amms(vec![AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(6),
+            ..Default::default()
+        })]);
+
+        assert_eq!(cloned_checkpoint.amms.len(), 6);
+        assert!(new_amms_rx.try_recv().is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_missing_pools() -> eyre::Result<()> {
+        let factory_address = H160::from_low_u64_be(100);
+        let tracked_pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(1),
+            ..Default::default()
+        });
+
+        let checkpoint = Checkpoint::new(
+            0,
+            10,
+            vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+                factory_address,
+                0,
+                300,
+            ))],
+            vec![tracked_pool],
+        );
+
+        let logs: Vec<Log> = (1..=3)
+            .map(|i| Log {
+                address: factory_address,
+                topics: vec![
+                    PAIR_CREATED_EVENT_SIGNATURE,
+                    H256::from(H160::from_low_u64_be(i * 10)),
+                    H256::from(H160::from_low_u64_be(i * 10 + 1)),
+                ],
+                data: ethers::abi::encode(&[
+                    Token::Address(H160::from_low_u64_be(i)),
+                    Token::Uint(U256::from(i)),
+                ])
+                .into(),
+                block_number: Some(U64::from(15)),
+                ..Default::default()
+            })
+            .collect();
+
+        let (middleware, mock) = Provider::mocked();
+        mock.push::<Vec<Log>, _>(logs)?;
+
+        let missing_pools = checkpoint
+            .find_missing_pools(10, 20, 100, Arc::new(middleware))
+            .await?;
+
+        assert_eq!(
+            missing_pools,
+            vec![H160::from_low_u64_be(2), H160::from_low_u64_be(3)]
+        );
+        assert_eq!(checkpoint.amms.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_new_from_factories_parallel() -> eyre::Result<()> {
+        let factory_address = H160::from_low_u64_be(100);
+        let factories = vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+            factory_address,
+            10,
+            300,
+        ))];
+
+        let mut logs = vec![];
+        let mut pool_data = vec![];
+        for i in 1..=3 {
+            let token_0 = H160::from_low_u64_be(i * 10);
+            let token_1 = H160::from_low_u64_be(i * 10 + 1);
+
+            logs.push(Log {
+                address: factory_address,
+                topics: vec![
+                    PAIR_CREATED_EVENT_SIGNATURE,
+                    H256::from(token_0),
+                    H256::from(token_1),
+                ],
+                data: ethers::abi::encode(&[
+                    Token::Address(H160::from_low_u64_be(i)),
+                    Token::Uint(U256::from(i)),
+                ])
+                .into(),
+                block_number: Some(U64::from(15)),
+                ..Default::default()
+            });
+
+            pool_data.push(Token::Tuple(vec![
+                Token::Address(token_0),
+                Token::Uint(U256::from(18)),
+                Token::Address(token_1),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(1000)),
+            ]));
+        }
+
+        //Mocked responses are returned in reverse order, each pair reports its own fee via `fee()`
+        let (middleware, mock) = Provider::mocked();
+        for i in (1..=3).rev() {
+            mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Uint(
+                U256::from(i * 100),
+            )])))?;
+        }
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(pool_data)])))?;
+        mock.push::<Vec<Log>, _>(logs)?;
+        mock.push::<U64, _>(U64::from(20))?;
+
+        let checkpoint =
+            Checkpoint::new_from_factories_parallel(factories, 100, Arc::new(middleware)).await?;
+
+        assert_eq!(checkpoint.block_number, 20);
+        assert_eq!(checkpoint.factories.len(), 1);
+        assert_eq!(checkpoint.amms.len(), 3);
+
+        for (i, amm) in checkpoint.amms.iter().enumerate() {
+            assert_eq!(amm.address(), H160::from_low_u64_be(i as u64 + 1));
+            if let AMM::UniswapV2Pool(pool) = amm {
+                assert_eq!(pool.fee, (i as u32 + 1) * 100);
+                assert_eq!(pool.reserve_0, 1000);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize_block_number() -> eyre::Result<()> {
+        for (block_number, expected) in [
+            (r#""block_number": 17000000,"#, 17000000),
+            (r#""block_number": "0x1036640","#, 17000000),
+            (r#""block_number": null,"#, 0),
+            ("", 0),
+        ] {
+            let checkpoint: Checkpoint = serde_json::from_str(&format!(
+                r#"{{"timestamp": 0, {block_number} "factories": [], "amms": []}}"#
+            ))?;
+
+            assert_eq!(checkpoint.block_number, expected);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_ids() -> eyre::Result<()> {
+        let mainnet_factory = Factory::UniswapV2Factory(UniswapV2Factory::new(
+            H160::from_str("0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f")?,
+            10000835,
+            300,
+        ));
+        let arbitrum_factory = Factory::UniswapV2Factory(UniswapV2Factory::new(
+            H160::from_str("0xf1D7CC64Fb4452F05c498126312eBE29f30Fbcf9")?,
+            150442611,
+            300,
+        ));
+        let unknown_factory =
+            Factory::UniswapV2Factory(UniswapV2Factory::new(H160::from_low_u64_be(1), 0, 300));
+        let pool = |address: u64, factory: &Factory| {
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                factory: factory.address(),
+                ..UniswapV2Pool::test_pool(address, H160::zero(), H160::zero(), 0, 0)
+            })
+        };
+
+        let checkpoint = Checkpoint::new(
+            0,
+            100,
+            vec![
+                mainnet_factory.clone(),
+                arbitrum_factory.clone(),
+                unknown_factory.clone(),
+                mainnet_factory.clone(),
+            ],
+            vec![
+                pool(2, &mainnet_factory),
+                pool(3, &arbitrum_factory),
+                pool(4, &mainnet_factory),
+                pool(5, &unknown_factory),
+            ],
+        );
+
+        assert_eq!(checkpoint.chain_ids(), vec![1, 42161]);
+
+        let mainnet_checkpoint = checkpoint.for_chain(1);
+        assert_eq!(mainnet_checkpoint.factories.len(), 2);
+        assert_eq!(mainnet_checkpoint.block_number, 100);
+        assert_eq!(
+            mainnet_checkpoint
+                .amms
+                .iter()
+                .map(|amm| amm.address())
+                .collect::<Vec<H160>>(),
+            vec![H160::from_low_u64_be(2), H160::from_low_u64_be(4)]
+        );
+
+        let arbitrum_checkpoint = checkpoint.for_chain(42161);
+        assert_eq!(arbitrum_checkpoint.factories.len(), 1);
+        assert_eq!(arbitrum_checkpoint.amms.len(), 1);
+        assert_eq!(
+            arbitrum_checkpoint.amms[0].address(),
+            H160::from_low_u64_be(3)
+        );
+
+        let optimism_checkpoint = checkpoint.for_chain(10);
+        assert!(optimism_checkpoint.factories.is_empty());
+        assert!(optimism_checkpoint.amms.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() {
+        let factory_address = H160::from_low_u64_be(100);
+        let pool = |address: u64, token_a: u64, creation_block: u64| {
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                creation_block,
+                ..UniswapV2Pool::test_pool(
+                    address,
+                    H160::from_low_u64_be(token_a),
+                    H160::from_low_u64_be(20),
+                    0,
+                    0,
+                )
+            })
+        };
+
+        let checkpoint = Checkpoint::new(
+            0,
+            100,
+            vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+                factory_address,
+                0,
+                300,
+            ))],
+            vec![pool(1, 10, 50), pool(2, 11, 60)],
+        );
+        assert_eq!(checkpoint.validate(), Ok(()));
+
+        let checkpoint = Checkpoint::new(
+            0,
+            100,
+            vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+                factory_address,
+                200,
+                300,
+            ))],
+            vec![
+                pool(1, 10, 50),
+                pool(0, 10, 50),
+                pool(1, 10, 50),
+                pool(2, 0, 50),
+                pool(3, 10, 150),
+            ],
+        );
+
+        assert_eq!(
+            checkpoint.validate(),
+            Err(vec![
+                CheckpointIssue::FactoryAheadOfCheckpoint(factory_address, 200),
+                CheckpointIssue::ZeroAddressAmm,
+                CheckpointIssue::DuplicateAmm(H160::from_low_u64_be(1)),
+                CheckpointIssue::ZeroAddressToken(H160::from_low_u64_be(2)),
+                CheckpointIssue::AmmAheadOfCheckpoint(H160::from_low_u64_be(3), 150),
+            ])
+        );
+
+        let checkpoint = Checkpoint::new(
+            0,
+            0,
+            vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+                factory_address,
+                200,
+                300,
+            ))],
+            vec![pool(1, 10, 50), pool(2, 11, 150)],
+        );
+
+        assert_eq!(
+            checkpoint.validate(),
+            Err(vec![CheckpointIssue::UnknownBlockNumber])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync_metadata_only() -> eyre::Result<()> {
+        let factory_address = H160::from_low_u64_be(100);
+        let token_a = H160::from_low_u64_be(10);
+        let token_b = H160::from_low_u64_be(11);
+        let tracked_pool = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: H160::from_low_u64_be(1),
+            token_a,
+            token_b,
+            reserve_0: 1000,
+            reserve_1: 2000,
+            fee: 300,
+            ..Default::default()
+        });
+
+        let mut checkpoint = Checkpoint::new(
+            0,
+            10,
+            vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+                factory_address,
+                0,
+                300,
+            ))],
+            vec![tracked_pool],
+        );
+
+        let new_pool_log = Log {
+            address: factory_address,
+            topics: vec![
+                PAIR_CREATED_EVENT_SIGNATURE,
+                H256::from(token_a),
+                H256::from(token_b),
+            ],
+            data: ethers::abi::encode(&[
+                Token::Address(H160::from_low_u64_be(2)),
+                Token::Uint(U256::from(2)),
+            ])
+            .into(),
+            block_number: Some(U64::from(15)),
+            ..Default::default()
+        };
+
+        //Pool data reporting reserves that differ from the checkpoint
+        let pool_data = Bytes::from(ethers::abi::encode(&[Token::Array(vec![Token::Tuple(
+            vec![
+                Token::Address(token_a),
+                Token::Uint(U256::from(6)),
+                Token::Address(token_b),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(5000)),
+                Token::Uint(U256::from(6000)),
+            ],
+        )])]));
+        let k_last = Bytes::from(ethers::abi::encode(&[Token::Uint(U256::zero())]));
+
+        //Mocked responses are returned in reverse order
+        let (middleware, mock) = Provider::mocked();
+        for _ in 0..2 {
+            //Standard pairs do not implement `fee()`
+            mock.push_response(MockResponse::Error(JsonRpcError {
+                code: 3,
+                message: "execution reverted".to_string(),
+                data: None,
+            }));
+            mock.push::<Bytes, _>(k_last.clone())?;
+            mock.push::<Bytes, _>(pool_data.clone())?;
+        }
+        mock.push::<Vec<Log>, _>(vec![new_pool_log])?;
+        mock.push::<U64, _>(U64::from(20))?;
+
+        let report = checkpoint
+            .sync_metadata_only(100, Arc::new(middleware))
+            .await?;
+
+        assert_eq!(report.new_amms, 1);
+        assert_eq!(report.amms_with_metadata_filled, 2);
+        assert_eq!(checkpoint.block_number, 10);
+
+        let pools: Vec<&UniswapV2Pool> = checkpoint
+            .amms
+            .iter()
+            .map(|amm| amm.try_into().unwrap())
+            .collect();
+
+        assert_eq!(pools[0].token_a_decimals, 6);
+        assert_eq!(pools[0].token_b_decimals, 18);
+        assert_eq!((pools[0].reserve_0, pools[0].reserve_1), (1000, 2000));
+
+        assert_eq!(pools[1].address, H160::from_low_u64_be(2));
+        assert_eq!(pools[1].token_a_decimals, 6);
+        assert_eq!((pools[1].reserve_0, pools[1].reserve_1), (0, 0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_from_file_filtered() -> eyre::Result<()> {
+        let amms: Vec<AMM> = (1..=100)
+            .map(|i| {
+                AMM::UniswapV2Pool(UniswapV2Pool {
+                    address: H160::from_low_u64_be(i),
+                    token_a: H160::from_low_u64_be(1000 + i),
+                    token_b: H160::from_low_u64_be(2000 + i),
+                    reserve_0: i as u128,
+                    reserve_1: i as u128 * 2,
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let path = std::env::temp_dir().join(format!(
+            "amms_filtered_checkpoint_{}.json",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+
+        construct_checkpoint(
+            vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+                H160::from_low_u64_be(5000),
+                0,
+                300,
+            ))],
+            &amms,
+            12345,
+            path,
+        )?;
+
+        let addresses = HashSet::from([
+            H160::from_low_u64_be(3),
+            H160::from_low_u64_be(50),
+            H160::from_low_u64_be(99),
+            H160::from_low_u64_be(500),
+        ]);
+        let checkpoint = Checkpoint::new_from_file_filtered(path, &addresses);
+        std::fs::remove_file(path)?;
+        let checkpoint = checkpoint?;
+
+        assert_eq!(checkpoint.block_number, 12345);
+        assert_eq!(checkpoint.factories.len(), 1);
+        assert_eq!(
+            checkpoint
+                .amms
+                .iter()
+                .map(|amm| amm.address())
+                .collect::<Vec<H160>>(),
+            vec![
+                H160::from_low_u64_be(3),
+                H160::from_low_u64_be(50),
+                H160::from_low_u64_be(99)
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_factories_configured() -> eyre::Result<()> {
+        let mut checkpoint = Checkpoint::new(0, 0, vec![], vec![]);
+
+        //No responses are mocked, the error must be returned before any request is made
+        let (middleware, _) = Provider::mocked();
+        let middleware = Arc::new(middleware);
+
+        assert!(matches!(
+            checkpoint.find_new_amms(100, middleware.clone()).await,
+            Err(AMMError::NoFactoriesConfigured)
+        ));
+        assert!(matches!(
+            checkpoint
+                .find_missing_pools(0, 100, 100, middleware.clone())
+                .await,
+            Err(AMMError::NoFactoriesConfigured)
+        ));
+        assert!(matches!(
+            Checkpoint::new_from_factories_parallel(vec![], 100, middleware).await,
+            Err(AMMError::NoFactoriesConfigured)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_from_rpc_with_retry() -> eyre::Result<()> {
+        let retry_config = RetryConfig {
+            max_retries: 2,
+            initial_delay_ms: 1,
+        };
+        let factories = vec![Factory::UniswapV2Factory(UniswapV2Factory::new(
+            H160::from_low_u64_be(100),
+            10,
+            300,
+        ))];
+
+        let rate_limit_error = || {
+            MockResponse::Error(JsonRpcError {
+                code: 429,
+                message: "Too Many Requests".to_string(),
+                data: None,
+            })
+        };
+
+        let pool_address = H160::from_low_u64_be(1);
+        let pool_data = Bytes::from(ethers::abi::encode(&[Token::Array(vec![Token::Tuple(
+            vec![
+                Token::Address(H160::from_low_u64_be(10)),
+                Token::Uint(U256::from(18)),
+                Token::Address(H160::from_low_u64_be(11)),
+                Token::Uint(U256::from(18)),
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(1000)),
+            ],
+        )])]));
+
+        //Mocked responses are returned in reverse order. Only the rate limited phase is retried, retrying the discovery
+        //would consume the pool data as the pair count
+        let (middleware, mock) = Provider::mocked();
+        let middleware = Arc::new(middleware);
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: None,
+        }));
+        mock.push::<Bytes, _>(pool_data)?;
+        mock.push_response(rate_limit_error());
+        mock.push_response(rate_limit_error());
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[Token::Array(vec![
+            Token::Address(pool_address),
+        ])])))?;
+        mock.push::<Bytes, _>(Bytes::from(ethers::abi::encode(&[
+            Token::Uint(U256::one()),
+        ])))?;
+        mock.push::<U64, _>(U64::from(20))?;
+        mock.push_response(rate_limit_error());
+
+        let checkpoint = Checkpoint::from_rpc_with_retry(
+            factories.clone(),
+            100,
+            middleware.clone(),
+            retry_config,
+        )
+        .await?;
+        assert_eq!(checkpoint.block_number, 20);
+        assert_eq!(checkpoint.factories.len(), 1);
+        assert_eq!(checkpoint.amms.len(), 1);
+        assert_eq!(checkpoint.amms[0].address(), pool_address);
+        assert_eq!(checkpoint.amms[0].reserves(), vec![1000, 1000]);
+
+        //Retries are exhausted
+        mock.push_response(rate_limit_error());
+        mock.push_response(rate_limit_error());
+        mock.push_response(rate_limit_error());
+        assert!(Checkpoint::from_rpc_with_retry(
+            factories.clone(),
+            100,
+            middleware.clone(),
+            retry_config
+        )
+        .await
+        .is_err());
+
+        //Other errors are not retried
+        mock.push::<U64, _>(U64::from(20))?;
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: -32000,
+            message: "header not found".to_string(),
+            data: None,
+        }));
+        assert!(
+            Checkpoint::from_rpc_with_retry(factories, 100, middleware.clone(), retry_config)
+                .await
+                .is_err()
+        );
+
+        assert!(matches!(
+            Checkpoint::from_rpc_with_retry(vec![], 100, middleware, retry_config).await,
+            Err(AMMError::NoFactoriesConfigured)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_rate_limit_message() {
+        assert!(is_rate_limit_message(
+            "(code: 429, message: Too Many Requests, data: None)"
+        ));
+        assert!(is_rate_limit_message("server returned status code 429"));
+        assert!(is_rate_limit_message("rate limit exceeded"));
+        assert!(!is_rate_limit_message(
+            "execution reverted at 0x4290000000000000000000000000000000000429"
+        ));
+        assert!(!is_rate_limit_message(
+            "header not found for block 17429000"
+        ));
+    }
+
+    #[test]
+    fn test_price_in() {
+        let token_a = H160::from_low_u64_be(1);
+        let token_b = H160::from_low_u64_be(2);
+        let token_c = H160::from_low_u64_be(3);
+
+        let pool_ab = AMM::UniswapV2Pool(UniswapV2Pool::test_pool(
+            10,
+            token_a,
+            token_b,
+            1_000_000_000_000_000_000,
+            2_000_000_000_000_000_000,
+        ));
+        let pool_bc = AMM::UniswapV2Pool(UniswapV2Pool::test_pool(
+            11,
+            token_b,
+            token_c,
+            1_000_000_000_000_000_000,
+            3_000_000_000_000_000_000,
+        ));
+
+        let checkpoint = Checkpoint::new(0, 100, vec![], vec![pool_ab.clone(), pool_bc.clone()]);
+
+        let expected_price =
+            pool_ab.calculate_price(token_a).unwrap() * pool_bc.calculate_price(token_b).unwrap();
+        let price = checkpoint.price_in(token_a, token_c, 2).unwrap();
+
+        assert!((price - expected_price).abs() < 1e-9);
+        assert!((price - 6.0).abs() < 1e-6);
+
+        assert!(checkpoint.price_in(token_a, token_c, 1).is_none());
+        assert_eq!(checkpoint.price_in(token_a, token_a, 0), Some(1.0));
+    }
+}
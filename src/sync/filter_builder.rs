@@ -0,0 +1,164 @@
+use ethers::types::{Filter, H160, H256};
+
+use crate::amm::{
+    factory::{AutomatedMarketMakerFactory, Factory},
+    AutomatedMarketMaker, AMM,
+};
+
+/// Builds the log filters used to discover and sync AMMs across multiple AMM variants.
+///
+/// Event signatures and addresses are deduplicated. By default the filters match logs from any address,
+/// call `restrict_by_address` to only match logs emitted by the supplied factories and AMMs.
+#[derive(Debug, Clone, Default)]
+pub struct FilterBuilder {
+    discovery_signatures: Vec<H256>,
+    factory_addresses: Vec<H160>,
+    sync_signatures: Vec<H256>,
+    amm_addresses: Vec<H160>,
+    restrict_by_address: bool,
+}
+
+impl FilterBuilder {
+    pub fn new() -> Self {
+        FilterBuilder::default()
+    }
+
+    /// Adds the creation event signatures and addresses of the factories.
+    pub fn with_factories<'a>(mut self, factories: impl IntoIterator<Item = &'a Factory>) -> Self {
+        for factory in factories {
+            push_unique(
+                &mut self.discovery_signatures,
+                factory.amm_created_event_signature(),
+            );
+            push_unique(&mut self.factory_addresses, factory.address());
+        }
+
+        self
+    }
+
+    /// Adds the sync event signatures and addresses of the AMMs.
+    pub fn with_amms<'a>(mut self, amms: impl IntoIterator<Item = &'a AMM>) -> Self {
+        for amm in amms {
+            for event_signature in amm.sync_on_event_signatures() {
+                push_unique(&mut self.sync_signatures, event_signature);
+            }
+
+            push_unique(&mut self.amm_addresses, amm.address());
+        }
+
+        self
+    }
+
+    /// Restricts the filters to logs emitted by the supplied factories and AMMs.
+    pub fn restrict_by_address(mut self) -> Self {
+        self.restrict_by_address = true;
+        self
+    }
+
+    /// Returns the filter matching the creation events of the supplied factories.
+    pub fn discovery_filter(&self) -> Filter {
+        let filter = Filter::new().topic0(self.discovery_signatures.clone());
+
+        if self.restrict_by_address {
+            filter.address(self.factory_addresses.clone())
+        } else {
+            filter
+        }
+    }
+
+    /// Returns the filter matching the sync events of the supplied AMMs.
+    pub fn sync_filter(&self) -> Filter {
+        let filter = Filter::new().topic0(self.sync_signatures.clone());
+
+        if self.restrict_by_address {
+            filter.address(self.amm_addresses.clone())
+        } else {
+            filter
+        }
+    }
+}
+
+fn push_unique<T: PartialEq>(values: &mut Vec<T>, value: T) {
+    if !values.contains(&value) {
+        values.push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{Filter, ValueOrArray, H160};
+
+    use crate::amm::{
+        erc_4626::{ERC4626Vault, DEPOSIT_EVENT_SIGNATURE, WITHDRAW_EVENT_SIGNATURE},
+        factory::Factory,
+        uniswap_v2::{
+            factory::{UniswapV2Factory, PAIR_CREATED_EVENT_SIGNATURE},
+            UniswapV2Pool, SYNC_EVENT_SIGNATURE,
+        },
+        AMM,
+    };
+
+    use super::FilterBuilder;
+
+    #[test]
+    fn test_filter_builder() {
+        let factory_address = H160::from_low_u64_be(100);
+        let factories = vec![
+            Factory::UniswapV2Factory(UniswapV2Factory::new(factory_address, 0, 300)),
+            Factory::UniswapV2Factory(UniswapV2Factory::new(factory_address, 0, 300)),
+        ];
+
+        let amms = vec![
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(1),
+                ..Default::default()
+            }),
+            AMM::UniswapV2Pool(UniswapV2Pool {
+                address: H160::from_low_u64_be(2),
+                ..Default::default()
+            }),
+            AMM::ERC4626Vault(ERC4626Vault {
+                vault_token: H160::from_low_u64_be(3),
+                ..Default::default()
+            }),
+        ];
+
+        let builder = FilterBuilder::new()
+            .with_factories(&factories)
+            .with_amms(&amms);
+
+        let discovery_filter = builder.discovery_filter();
+        assert_eq!(
+            discovery_filter.topics[0],
+            Filter::new()
+                .topic0(vec![PAIR_CREATED_EVENT_SIGNATURE])
+                .topics[0]
+        );
+        assert!(discovery_filter.address.is_none());
+
+        let sync_filter = builder.sync_filter();
+        assert_eq!(
+            sync_filter.topics[0],
+            Filter::new()
+                .topic0(vec![
+                    SYNC_EVENT_SIGNATURE,
+                    DEPOSIT_EVENT_SIGNATURE,
+                    WITHDRAW_EVENT_SIGNATURE,
+                ])
+                .topics[0]
+        );
+        assert!(sync_filter.address.is_none());
+
+        let builder = builder.restrict_by_address();
+        assert_eq!(
+            builder.discovery_filter().address,
+            Some(ValueOrArray::Array(vec![factory_address]))
+        );
+        assert_eq!(
+            builder.sync_filter().address,
+            Some(ValueOrArray::Array(
+                (1..=3).map(H160::from_low_u64_be).collect()
+            ))
+        );
+    }
+}
@@ -11,6 +11,9 @@ use ethers::providers::Middleware;
 
 use std::{panic::resume_unwind, sync::Arc};
 pub mod checkpoint;
+pub mod filter_builder;
+pub mod serde_with;
+pub mod watcher;
 /// Syncs all AMMs from the supplied factories.
 ///
 /// factories - A vector of factories to sync AMMs from.
@@ -54,13 +57,11 @@ pub async fn sync_amms<M: 'static + Middleware>(
             //Clean empty pools
             amms = filters::filter_empty_amms(amms);
 
-            //If the factory is UniswapV2, set the fee for each pool according to the factory fee
-            if let Factory::UniswapV2Factory(factory) = factory {
-                for amm in amms.iter_mut() {
-                    if let AMM::UniswapV2Pool(ref mut pool) = amm {
-                        pool.fee = factory.fee;
-                    }
-                }
+            //Pools start with the factory fee, forks with per pair fees report them via `fee()` on the pair
+            if let Factory::UniswapV2Factory(factory) = &factory {
+                factory
+                    .populate_pair_fees(&mut amms, Some(current_block), middleware.clone())
+                    .await?;
             }
 
             Ok::<_, AMMError<M>>(amms)
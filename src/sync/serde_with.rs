@@ -0,0 +1,60 @@
+use serde::{de::Error, Deserialize, Deserializer};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString {
+    Number(u64),
+    String(String),
+}
+
+/// Deserializes an optional `u64` from `null`, a JSON number or a string holding a hex (`0x` prefixed) or decimal number.
+pub fn deserialize_optional_u64<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+    match Option::<NumberOrString>::deserialize(d)? {
+        None => Ok(None),
+        Some(NumberOrString::Number(value)) => Ok(Some(value)),
+        Some(NumberOrString::String(value)) => {
+            let parsed = match value.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => value.parse(),
+            };
+
+            parsed
+                .map(Some)
+                .map_err(|_| D::Error::custom(format!("invalid u64: {value}")))
+        }
+    }
+}
+
+/// Deserializes a `u64` with `deserialize_optional_u64`, defaulting `null` to zero.
+pub fn deserialize_u64_or_default<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+    Ok(deserialize_optional_u64(d)?.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::deserialize_optional_u64;
+
+    #[derive(Deserialize)]
+    struct BlockNumber {
+        #[serde(default, deserialize_with = "deserialize_optional_u64")]
+        block_number: Option<u64>,
+    }
+
+    #[test]
+    fn test_deserialize_optional_u64() -> eyre::Result<()> {
+        let parse = |json: &str| -> eyre::Result<Option<u64>> {
+            Ok(serde_json::from_str::<BlockNumber>(json)?.block_number)
+        };
+
+        assert_eq!(parse(r#"{"block_number": null}"#)?, None);
+        assert_eq!(parse(r#"{"block_number": 17000000}"#)?, Some(17000000));
+        assert_eq!(parse(r#"{"block_number": "0x1036640"}"#)?, Some(17000000));
+        assert_eq!(parse(r#"{"block_number": "17000000"}"#)?, Some(17000000));
+        assert_eq!(parse("{}")?, None);
+        assert!(parse(r#"{"block_number": "0xzz"}"#).is_err());
+
+        Ok(())
+    }
+}
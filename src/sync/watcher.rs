@@ -0,0 +1,153 @@
+use std::sync::Arc;
+
+use ethers::{
+    providers::{Middleware, PubsubClient, StreamExt},
+    types::{Filter, Log},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    amm::{AutomatedMarketMaker, AMM},
+    errors::{AMMError, EventLogError},
+};
+
+pub type OnUpdate = Box<dyn Fn(&AMM, &AMM) + Send + Sync>;
+
+/// Watches a single AMM, applying its logs as they are emitted and calling `on_update` with the state before and after each log.
+///
+/// This is a lightweight alternative to the `StateSpaceManager` for monitoring a single pool.
+pub struct PoolWatcher<M> {
+    amm: AMM,
+    middleware: Arc<M>,
+    on_update: OnUpdate,
+    cancellation_token: CancellationToken,
+}
+
+impl<M> PoolWatcher<M>
+where
+    M: Middleware + 'static,
+{
+    pub fn new<F>(amm: AMM, middleware: Arc<M>, on_update: F) -> Self
+    where
+        F: Fn(&AMM, &AMM) + Send + Sync + 'static,
+    {
+        Self {
+            amm,
+            middleware,
+            on_update: Box::new(on_update),
+            cancellation_token: CancellationToken::new(),
+        }
+    }
+
+    /// Returns the current state of the watched AMM.
+    pub fn amm(&self) -> &AMM {
+        &self.amm
+    }
+
+    /// Returns a token that stops `watch` when cancelled.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation_token.clone()
+    }
+
+    /// Returns the filter for the logs that update the watched AMM.
+    pub fn filter(&self) -> Filter {
+        Filter::new()
+            .address(self.amm.address())
+            .topic0(self.amm.sync_on_event_signatures())
+    }
+
+    /// Applies the log to the watched AMM and calls `on_update` with the state before and after the log.
+    pub fn apply_log(&mut self, log: Log) -> Result<(), EventLogError> {
+        let old_amm = self.amm.clone();
+        self.amm.sync_from_log(log)?;
+
+        (self.on_update)(&old_amm, &self.amm);
+
+        Ok(())
+    }
+}
+
+impl<M> PoolWatcher<M>
+where
+    M: Middleware + 'static,
+    M::Provider: PubsubClient,
+{
+    /// Subscribes to the logs of the watched AMM and applies them until the cancellation token is cancelled or the subscription ends.
+    pub async fn watch(&mut self) -> Result<(), AMMError<M>> {
+        let middleware = self.middleware.clone();
+        let filter = self.filter();
+        let cancellation_token = self.cancellation_token.clone();
+
+        let mut log_stream = middleware
+            .subscribe_logs(&filter)
+            .await
+            .map_err(AMMError::MiddlewareError)?;
+
+        loop {
+            tokio::select! {
+                _ = cancellation_token.cancelled() => break,
+                log = log_stream.next() => match log {
+                    Some(log) => self.apply_log(log)?,
+                    None => break,
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use ethers::{
+        abi::Token,
+        providers::Provider,
+        types::{Log, H160, U256},
+    };
+
+    use crate::amm::{
+        uniswap_v2::{UniswapV2Pool, SYNC_EVENT_SIGNATURE},
+        AMM,
+    };
+
+    use super::PoolWatcher;
+
+    #[test]
+    fn test_apply_log() -> eyre::Result<()> {
+        let pool_address = H160::from_low_u64_be(1);
+        let amm = AMM::UniswapV2Pool(UniswapV2Pool {
+            address: pool_address,
+            reserve_0: 100,
+            reserve_1: 100,
+            ..Default::default()
+        });
+
+        let updates = Arc::new(Mutex::new(vec![]));
+        let (middleware, _) = Provider::mocked();
+
+        let on_update_updates = updates.clone();
+        let mut watcher = PoolWatcher::new(amm, Arc::new(middleware), move |old, new| {
+            if let (AMM::UniswapV2Pool(old), AMM::UniswapV2Pool(new)) = (old, new) {
+                on_update_updates
+                    .lock()
+                    .unwrap()
+                    .push((old.reserve_0, new.reserve_0));
+            }
+        });
+
+        watcher.apply_log(Log {
+            address: pool_address,
+            topics: vec![SYNC_EVENT_SIGNATURE],
+            data: ethers::abi::encode(&[Token::Uint(U256::from(150)), Token::Uint(U256::from(80))])
+                .into(),
+            ..Default::default()
+        })?;
+
+        assert_eq!(*updates.lock().unwrap(), vec![(100, 150)]);
+        assert!(!watcher.cancellation_token().is_cancelled());
+
+        Ok(())
+    }
+}